@@ -0,0 +1,377 @@
+//! Uninteresting parsing impls
+
+use super::*;
+
+/// The parsed `#[with(...)]` attribute arguments, _e.g._,
+/// `'local, continuation_name = ret, dyn_safe = true`.
+///
+/// The omitted ones default to those of the `[package.metadata.with_locals]`
+/// table of the `Cargo.toml` of the crate being compiled, if any.
+///
+/// Obtained through its [`Parse`] implementation, _e.g._, with
+/// [`syn::parse2`].
+#[derive(Clone)]
+pub
+struct Attrs {
+    pub(in crate) lifetime: Str,
+    pub(in crate) continuation: Option<Ident>,
+    /// `auto_return = <bool>`: whether `return <expr>` (and `?`, and the
+    /// tail expression) are to be rewritten as `return continuation(<expr>)`.
+    /// Defaults to the lack of a `continuation_name`, which disabling it
+    /// requires (hence the span of the key, to report its lack).
+    pub(in crate) auto_return: Option<(bool, Span)>,
+    pub(in crate) dyn_safe: DynSafe,
+    pub(in crate) recursive: bool,
+    pub(in crate) continuation_bounds: Punctuated<TypeParamBound, Token![+]>,
+    /// The name of the generated function, with `{}` standing for the name of
+    /// the original one (`name = "visit_{}"`), or an explicit one
+    /// (`rename = scoped_view`).
+    pub(in crate) name: Str,
+    /// The span of the `rename` key, if any: an explicit name only makes sense
+    /// for a single `fn`.
+    pub(in crate) rename: Option<Span>,
+    /// `crate = path::to::with_locals`: the path through which the generated
+    /// code refers to the `with_locals` crate (_e.g._, for a crate re-exporting
+    /// `#[with]`).
+    pub(in crate) krate: Option<Path>,
+    /// `macros = [bail, ensure]`: the (non-standard) macros whose arguments
+    /// are expressions to be transformed like the rest of the code.
+    pub(in crate) macros: Vec<Ident>,
+}
+
+/// How the continuation of a with-function is type-erased, if at all.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(in crate)
+enum DynSafe {
+    /// `dyn_safe = false`: a generic `impl FnOnce(...) -> R` continuation.
+    No,
+
+    /// `dyn_safe = true`: a `&mut dyn FnMut(...) -> ContinuationReturn`.
+    FnMut,
+
+    /// `dyn_safe = "boxed"`: a `Box<dyn FnOnce(...) -> ContinuationReturn>`.
+    Boxed,
+}
+
+impl DynSafe {
+    pub(in crate)
+    fn is_dyn_safe (self: DynSafe)
+      -> bool
+    {
+        self != DynSafe::No
+    }
+}
+
+/// `= <bool>`, `= "boxed"`, or nothing (the `#[with(dyn_safe)]` shorthand).
+impl Parse for DynSafe {
+    fn parse (input: ParseStream<'_>)
+      -> Result<Self>
+    {
+        if input.parse::<Option<Token![=]>>()?.is_none() {
+            return Ok(DynSafe::FnMut);
+        }
+        if input.peek(LitStr) {
+            let lit: LitStr = input.parse()?;
+            return match &*lit.value() {
+                | "boxed" => if cfg!(feature = "alloc") {
+                    Ok(DynSafe::Boxed)
+                } else {
+                    Err(Error::new_spanned(lit, "\
+                        `dyn_safe = \"boxed\"` requires the `alloc` \
+                        Cargo feature of `with_locals`\
+                    "))
+                },
+                | _ => Err(Error::new_spanned(lit,
+                    "expected `true`, `false`, or `\"boxed\"`",
+                )),
+            };
+        }
+        let bool_literal: LitBool = input.parse()?;
+        Ok(if bool_literal.value { DynSafe::FnMut } else { DynSafe::No })
+    }
+}
+
+impl Parse for Attrs {
+    fn parse (input: ParseStream<'_>)
+      -> Result<Self>
+    {
+        // Defaults to the crate-wide config, if any.
+        let config = config::config();
+        let ret = Self {
+            lifetime: config.lifetime.clone(),
+            continuation: None,
+            auto_return: None,
+            dyn_safe: config.dyn_safe,
+            recursive: config.recursive,
+            continuation_bounds: Punctuated::new(),
+            name: config.name.clone(),
+            rename: None,
+            krate: None,
+            macros: vec![],
+        };
+        Self::parse_over(ret, input)
+    }
+}
+
+impl Attrs {
+    /// Parses the `#[with(...)]` arguments as overrides of the given ones
+    /// (_e.g._, for the `#[with(...)]` attribute of a method of a `#[with]`
+    /// `impl`, which only changes the arguments it spells out).
+    pub(in crate)
+    fn parse_over (mut ret: Attrs, input: ParseStream<'_>)
+      -> Result<Attrs>
+    {
+        if let Some(lt) = input.parse::<Option<Lifetime>>()? {
+            ret.lifetime = lt.ident.to_string().into();
+            if input.parse::<Option<Token![,]>>()?.is_none() {
+                return Ok(ret);
+            }
+        }
+        mod kw {
+            ::syn::custom_keyword!(auto_return);
+            ::syn::custom_keyword!(continuation_bounds);
+            ::syn::custom_keyword!(continuation_name);
+            ::syn::custom_keyword!(dyn_safe);
+            ::syn::custom_keyword!(macros);
+            ::syn::custom_keyword!(name);
+            ::syn::custom_keyword!(recursive);
+            ::syn::custom_keyword!(rename);
+        }
+        while input.is_empty().not() {
+            match () {
+                | _case if input.peek(kw::recursive) => {
+                    input.parse::<kw::recursive>().unwrap();
+                    ret.recursive = parse_bool_or_shorthand(input)?;
+                    input.parse::<Option<Token![,]>>()?;
+                },
+                | _case if input.peek(kw::dyn_safe) => {
+                    input.parse::<kw::dyn_safe>().unwrap();
+                    ret.dyn_safe = input.parse()?;
+                    input.parse::<Option<Token![,]>>()?;
+                },
+                | _case if input.peek(kw::continuation_bounds) => {
+                    input.parse::<kw::continuation_bounds>().unwrap();
+                    input.parse::<Token![=]>()?;
+                    ret.continuation_bounds =
+                        Punctuated::parse_separated_nonempty(input)?
+                    ;
+                    input.parse::<Option<Token![,]>>()?;
+                },
+                | _case if input.peek(kw::auto_return) => {
+                    let kw = input.parse::<kw::auto_return>().unwrap();
+                    ret.auto_return = Some((
+                        parse_bool_or_shorthand(input)?,
+                        kw.span,
+                    ));
+                    input.parse::<Option<Token![,]>>()?;
+                },
+                | _case if input.peek(kw::continuation_name) => {
+                    input.parse::<kw::continuation_name>().unwrap();
+                    input.parse::<Token![=]>()?;
+                    ret.continuation.replace(input.parse()?);
+                    input.parse::<Option<Token![,]>>()?;
+                },
+                | _case if input.peek(kw::name) || input.peek(kw::rename) => {
+                    ret.rename = if input.peek(kw::rename) {
+                        Some(input.span())
+                    } else {
+                        None
+                    };
+                    ret.name = parse_name(input)?;
+                    input.parse::<Option<Token![,]>>()?;
+                },
+                | _case if input.peek(kw::macros) => {
+                    input.parse::<kw::macros>().unwrap();
+                    input.parse::<Token![=]>()?;
+                    let macros;
+                    bracketed!(macros in input);
+                    ret.macros.extend(
+                        Punctuated::<Ident, Token![,]>::parse_terminated(&macros)?
+                    );
+                    input.parse::<Option<Token![,]>>()?;
+                },
+                | _case if input.peek(Token![crate]) => {
+                    input.parse::<Token![crate]>().unwrap();
+                    input.parse::<Token![=]>()?;
+                    ret.krate.replace(Path::parse_mod_style(input)?);
+                    input.parse::<Option<Token![,]>>()?;
+                },
+                | _default => break,
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Parses the `#[with(...)]` arguments, falling back to the default ones
+    /// (but for the leading `'lifetime`, if any) on error, so that a
+    /// best-effort expansion can still be emitted.
+    pub(in crate)
+    fn parse_recovering (
+        tokens: TokenStream2,
+        encountered_error: &'_ mut Option<Error>,
+    ) -> Attrs
+    {
+        parse2(tokens.clone()).unwrap_or_else(|err| {
+            encountered_error.push_error(err);
+            let mut ret: Attrs = parse2(TokenStream2::new()).unwrap();
+            let leading_lifetime = |input: ParseStream<'_>| {
+                let lt = input.parse::<Option<Lifetime>>()?;
+                input.parse::<TokenStream2>()?;
+                Ok(lt)
+            };
+            if let Ok(Some(lt)) = ::syn::parse::Parser::parse2(leading_lifetime, tokens) {
+                ret.lifetime = lt.ident.to_string().into();
+            }
+            ret
+        })
+    }
+
+    /// `rename = ...` only makes sense for a single `fn`: on an `impl`,
+    /// `trait`, or `mod`, it is reported, and the default name used instead.
+    pub(in crate)
+    fn reject_rename<'attrs> (
+        self: &'attrs Attrs,
+        encountered_error: &'_ mut Option<Error>,
+    ) -> ::std::borrow::Cow<'attrs, Attrs>
+    {
+        match self.rename {
+            | None => ::std::borrow::Cow::Borrowed(self),
+            | Some(span) => {
+                encountered_error.push_error(Error::new(span, "\
+                    `rename = ...` can only be applied to a single `fn`; \
+                    use a `name = \"...{}...\"` pattern instead\
+                "));
+                ::std::borrow::Cow::Owned(Attrs {
+                    name: config::config().name.clone(),
+                    rename: None,
+                    ..self.clone()
+                })
+            },
+        }
+    }
+}
+
+/// `name = "<pattern>"` or `rename = <ident>`.
+fn parse_name (input: ParseStream<'_>)
+  -> Result<Str>
+{
+    mod kw {
+        ::syn::custom_keyword!(name);
+        ::syn::custom_keyword!(rename);
+    }
+    if input.parse::<Option<kw::rename>>()?.is_some() {
+        input.parse::<Token![=]>()?;
+        let ident: Ident = input.parse()?;
+        return Ok(ident.unraw().to_string().into());
+    }
+    input.parse::<kw::name>()?;
+    input.parse::<Token![=]>()?;
+    let pattern: LitStr = input.parse()?;
+    config::check_name_pattern(&pattern.value())
+        .map_err(|msg| Error::new_spanned(&pattern, msg))?;
+    Ok(pattern.value().into())
+}
+
+/// `= <bool>`, with the ` = true` part being optional (_e.g._,
+/// `#[with(recursive)]`).
+fn parse_bool_or_shorthand (input: ParseStream<'_>)
+  -> Result<bool>
+{
+    Ok(if input.parse::<Option<Token![=]>>()?.is_some() {
+        input.parse::<LitBool>()?.value
+    } else {
+        true
+    })
+}
+
+/// The (optional) parameters of a `#[with(...)] let` statement.
+#[derive(Default)]
+pub(in crate)
+struct CallSiteAttrs {
+    /// `dyn_safe`, `dyn_safe = <bool>`, or `dyn_safe = "boxed"`.
+    pub dyn_safe: Option<DynSafe>,
+
+    /// `move`: makes the continuation closure a `move` one (_e.g._, for
+    /// with-functions requiring a `'static` continuation).
+    pub move_: bool,
+
+    /// `name = "<pattern>"` or `rename = <ident>`: the name of the called
+    /// with-function, when it differs from the crate-wide one.
+    pub name: Option<Str>,
+
+    /// `ret = <type>`: the type the statements following the `#[with] let`
+    /// (_i.e._, the continuation) evaluate to, for when it cannot be inferred.
+    pub ret: Option<Type>,
+
+    /// `return = <type>`: the type of the values early-returned (through
+    /// `return` or `?`) from within the continuation.
+    pub return_: Option<Type>,
+
+    /// `break = <type>`: the type of the values `break`-ed with from within
+    /// the continuation.
+    pub break_: Option<Type>,
+}
+
+impl Parse for CallSiteAttrs {
+    fn parse (input: ParseStream<'_>)
+      -> Result<Self>
+    {
+        let mut ret = Self::default();
+        if input.is_empty() {
+            // Plain `#[with]`.
+            return Ok(ret);
+        }
+        let input = {
+            let content;
+            parenthesized!(content in input);
+            content
+        };
+        mod kw {
+            ::syn::custom_keyword!(dyn_safe);
+            ::syn::custom_keyword!(name);
+            ::syn::custom_keyword!(rename);
+            ::syn::custom_keyword!(ret);
+        }
+        while input.is_empty().not() {
+            match () {
+                | _case if input.peek(kw::dyn_safe) => {
+                    input.parse::<kw::dyn_safe>().unwrap();
+                    ret.dyn_safe = Some(input.parse()?);
+                    input.parse::<Option<Token![,]>>()?;
+                },
+                | _case if input.peek(Token![move]) => {
+                    input.parse::<Token![move]>().unwrap();
+                    ret.move_ = true;
+                    input.parse::<Option<Token![,]>>()?;
+                },
+                | _case if input.peek(kw::name) || input.peek(kw::rename) => {
+                    ret.name = Some(parse_name(&input)?);
+                    input.parse::<Option<Token![,]>>()?;
+                },
+                | _case if input.peek(kw::ret) => {
+                    input.parse::<kw::ret>().unwrap();
+                    input.parse::<Token![=]>()?;
+                    ret.ret = Some(input.parse()?);
+                    input.parse::<Option<Token![,]>>()?;
+                },
+                | _case if input.peek(Token![return]) => {
+                    input.parse::<Token![return]>().unwrap();
+                    input.parse::<Token![=]>()?;
+                    ret.return_ = Some(input.parse()?);
+                    input.parse::<Option<Token![,]>>()?;
+                },
+                | _case if input.peek(Token![break]) => {
+                    input.parse::<Token![break]>().unwrap();
+                    input.parse::<Token![=]>()?;
+                    ret.break_ = Some(input.parse()?);
+                    input.parse::<Option<Token![,]>>()?;
+                },
+                | _default => return Err(input.error(
+                    "expected `dyn_safe`, `move`, `name`, `rename`, `ret`, \
+                    `return`, or `break`",
+                )),
+            }
+        }
+        Ok(ret)
+    }
+}
@@ -0,0 +1,230 @@
+//! Crate-wide defaults for the `#[with]` arguments, read from the
+//! `[package.metadata.with_locals]` table of the `Cargo.toml` of the crate
+//! being compiled:
+//!
+//! ```toml
+//! [package.metadata.with_locals]
+//! lifetime = "local"   # Instead of `'ref`.
+//! dyn_safe = false     # Or `true`, or `"boxed"`.
+//! recursive = false
+//! name = "with_{}"     # The name of the generated function.
+//! ```
+//!
+//! Being read with an actual TOML parser, any TOML spelling of that table will
+//! do (_e.g._, `with_locals = { lifetime = "local" }` within
+//! `[package.metadata]`).
+//!
+//! Since procedural macros have no stable way to tell the compiler about the
+//! files they read, the bodies of the expanded functions start with a
+//! `const _: &[u8] = include_bytes!("<path>/Cargo.toml");`, so that changing
+//! the defaults re-expands the `#[with]` items (rather than incremental builds
+//! sticking to the stale ones).
+
+use super::*;
+
+use ::std::{
+    cell::RefCell,
+    env,
+    fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+const TABLE: &str = "[package.metadata.with_locals]";
+
+pub(in crate)
+struct Config {
+    pub(in crate) lifetime: Str,
+    pub(in crate) dyn_safe: DynSafe,
+    pub(in crate) recursive: bool,
+
+    /// The name of the generated function, with `{}` standing for the name of
+    /// the original one.
+    pub(in crate) name: Str,
+
+    /// Should the config be invalid, the built-in defaults are used, and this
+    /// is the message to report.
+    pub(in crate) error: Option<String>,
+}
+
+impl Default for Config {
+    fn default ()
+      -> Config
+    {
+        Config {
+            lifetime: "ref".into(),
+            dyn_safe: DynSafe::No,
+            recursive: false,
+            name: "with_{}".into(),
+            error: None,
+        }
+    }
+}
+
+::std::thread_local! {
+    static MANIFEST_PATH: RefCell<Option<PathBuf>> = const { RefCell::new(None) };
+}
+
+/// Runs `f` with the crate-wide defaults read from the given `Cargo.toml`,
+/// rather than from the one of the crate being compiled (as given by the
+/// `CARGO_MANIFEST_DIR` environment variable).
+///
+/// This is meant for tools applying the transformations outside of a Cargo
+/// build (_e.g._, `with-locals-expand`).
+pub
+fn with_manifest_path<R> (manifest_path: &'_ Path, f: impl FnOnce() -> R)
+  -> R
+{
+    let prev = MANIFEST_PATH.with(|it| it.replace(Some(manifest_path.to_owned())));
+    let ret = f();
+    MANIFEST_PATH.with(|it| *it.borrow_mut() = prev);
+    ret
+}
+
+/// The config of the crate being compiled, cached since it is queried by every
+/// `#[with]` invocation.
+pub(in crate)
+fn config ()
+  -> Rc<Config>
+{
+    thread_local! {
+        static CACHE: RefCell<Option<(Option<PathBuf>, Rc<Config>)>> = const {
+            RefCell::new(None)
+        }
+        ;
+    }
+    let manifest_path =
+        MANIFEST_PATH.with(|it| it.borrow().clone())
+            .or_else(|| {
+                env::var_os("CARGO_MANIFEST_DIR")
+                    .map(|dir| PathBuf::from(dir).join("Cargo.toml"))
+            })
+    ;
+    CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        match *cache {
+            | Some((ref path, ref config)) if *path == manifest_path => config.clone(),
+            | _ => {
+                let config = Rc::new(
+                    manifest_path
+                        .as_ref()
+                        .and_then(|path| fs::read_to_string(path).ok())
+                        .map_or_else(Config::default, |manifest| {
+                            parse_manifest(&manifest).unwrap_or_else(|err| Config {
+                                error: Some(format!(
+                                    "invalid `{}` in `Cargo.toml`: {}", TABLE, err,
+                                )),
+                                ..Config::default()
+                            })
+                        })
+                );
+                *cache = Some((manifest_path, config.clone()));
+                config
+            },
+        }
+    })
+}
+
+/// The `const _: &[u8] = include_bytes!("<path>/Cargo.toml");` statement
+/// making the crate being compiled depend on its manifest (see the module
+/// docs), when running within the compiler (rather than within, _e.g._,
+/// `with-locals-expand`).
+pub(in crate)
+fn manifest_dependency ()
+  -> Option<Stmt>
+{
+    if ::proc_macro::is_available().not()
+    || MANIFEST_PATH.with(|it| it.borrow().is_some())
+    {
+        return None;
+    }
+    let manifest_path = PathBuf::from(env::var_os("CARGO_MANIFEST_DIR")?).join("Cargo.toml");
+    if manifest_path.is_file().not() {
+        return None;
+    }
+    let manifest_path = manifest_path.to_str()?;
+    Some(parse_quote! {
+        const _: &[u8] = ::core::include_bytes!(#manifest_path);
+    })
+}
+
+fn parse_manifest (manifest: &'_ str)
+  -> ::core::result::Result<Config, String>
+{
+    let mut ret = Config::default();
+    let manifest: ::toml::Value = manifest.parse().map_err(|err| format!("{}", err))?;
+    let table =
+        ["package", "metadata", "with_locals"]
+            .iter()
+            .try_fold(&manifest, |value, &key| value.get(key))
+    ;
+    let table = match table {
+        | None => return Ok(ret),
+        | Some(::toml::Value::Table(table)) => table,
+        | Some(_) => return Err("expected a table".into()),
+    };
+    for (key, value) in table {
+        let string = || {
+            value.as_str().ok_or_else(|| format!("expected a string for `{}`", key))
+        };
+        let boolean = || {
+            value.as_bool().ok_or_else(|| {
+                format!("expected `true` or `false` for `{}`", key)
+            })
+        };
+        match &key[..] {
+            | "lifetime" => {
+                let lifetime = string()?;
+                let lifetime = lifetime.strip_prefix('\'').unwrap_or(lifetime);
+                if parse_str::<Lifetime>(&format!("'{}", lifetime)).is_err() {
+                    return Err(format!("invalid lifetime `'{}`", lifetime));
+                }
+                ret.lifetime = lifetime.to_owned().into();
+            },
+            | "dyn_safe" => {
+                ret.dyn_safe = match value.as_str() {
+                    | Some("boxed") if cfg!(feature = "alloc") => DynSafe::Boxed,
+                    | Some("boxed") => return Err(
+                        "`dyn_safe = \"boxed\"` requires the `alloc` Cargo \
+                        feature of `with_locals`".into()
+                    ),
+                    | Some(_) => return Err(
+                        "expected `true`, `false`, or `\"boxed\"` for `dyn_safe`".into()
+                    ),
+                    | None => if boolean()? { DynSafe::FnMut } else { DynSafe::No },
+                };
+            },
+            | "recursive" => ret.recursive = boolean()?,
+            | "name" => {
+                let name = string()?;
+                check_name_pattern(name)?;
+                ret.name = name.to_owned().into();
+            },
+            | _ => return Err(format!(
+                "unknown key `{}` (expected `lifetime`, `dyn_safe`, \
+                `recursive`, or `name`)",
+                key,
+            )),
+        }
+    }
+    Ok(ret)
+}
+
+/// A `name = "..."` pattern must be an identifier once its (single) `{}` is
+/// replaced with the name of the original function.
+pub(in crate)
+fn check_name_pattern (name: &'_ str)
+  -> ::core::result::Result<(), String>
+{
+    if name.matches("{}").count() != 1
+    || parse_str::<Ident>(&name.replace("{}", "f")).is_err()
+    || name == "{}"
+    {
+        return Err(format!(
+            "`name = \"{}\"` must be an identifier containing \
+            exactly one `{{}}` (_e.g._, `\"with_{{}}\"`)",
+            name,
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,375 @@
+//! The `expand-macros` feature: persisting each expansion to a file.
+//!
+//! Each expansion is written to
+//! `target/with_locals/<crate>/<module>::<name>.rs` (the
+//! `WITH_LOCALS_EXPAND_DIR` env var overrides the `target/with_locals` part),
+//! where `<name>` is the one of the `fn`, `trait` or `mod`, or
+//! `impl_<Trait>_for_<Type>` / `impl_<Type>`, suffixed with `.2`, `.3`, _etc._
+//! for the later items of the crate whose file name would otherwise be the
+//! same (_e.g._, two `impl`s of the same type), along with a header mentioning where the original item is located, and a
+//! side table mapping the statements of the original item to the lines of the
+//! expansion where they ended up.
+//!
+//! The location of the original item, as well as the side table, require the
+//! compiler to expose span locations to procedural macros, which it only does
+//! from Rust 1.88 onwards. With older compilers (such as our MSRV), the
+//! expansion is written all the same, but to `<crate>/<name>.rs`, and with a
+//! header saying that the locations are unavailable.
+
+use super::*;
+
+use ::proc_macro2::{
+    LineColumn,
+    TokenTree,
+};
+use ::std::{
+    cell::RefCell,
+    env,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// The marker statement inserted before each statement stemming from an
+/// original one, so as to find out its line number after formatting.
+const MARKER: &str = "__with_locals_stmt__";
+
+::std::thread_local! {
+    /// The files written so far by the compilation of the current crate.
+    static WRITTEN: RefCell<Vec<PathBuf>> = const { RefCell::new(Vec::new()) };
+}
+
+#[cfg(not(windows))]
+const MODULE_SEPARATOR: &str = "::";
+#[cfg(windows)] // `:` is not allowed in file names.
+const MODULE_SEPARATOR: &str = ".";
+
+pub(in crate)
+fn persist_expansion (
+    input: &'_ TokenStream2,
+    expansion: &'_ TokenStream2,
+    name: &'_ str,
+)
+{
+    if matches!(
+        env::var("WITH_LOCALS_DEBUG_FILTER"),
+        Ok(ref filter) if name.contains(filter).not()
+    )
+    {
+        return;
+    }
+    let call_site = Span::call_site().start();
+    // Both are unavailable before Rust 1.88.
+    let has_locations = call_site.line != 0;
+    let source_file = if has_locations { source_file() } else { None };
+    let location = |LineColumn { line, column }: LineColumn| format!(
+        "{}:{}:{}",
+        source_file.as_ref().map_or("<unknown>".into(), |it| it.display().to_string()),
+        line,
+        column + 1,
+    );
+
+    // Mark the statements of the expansion stemming from original ones.
+    let mut stmts = vec![];
+    StmtRanges(&mut stmts).visit_file_mut(&mut as_file(input));
+    let mut marker = Marker {
+        stmts: stmts.into_iter().map(|range| (range, false)).collect(),
+        marked: vec![],
+        call_site,
+    };
+    let (code, wrapped) = {
+        let mut file = as_file(expansion);
+        marker.visit_file_mut(&mut file);
+        (file.into_token_stream().to_string(), expansion_is_wrapped(expansion))
+    };
+    let formatted = helpers::rustfmt(&code).and_then(|formatted| {
+        let mut table = vec![];
+        let mut lines = vec![];
+        let mut inner = formatted.lines().collect::<Vec<_>>();
+        if wrapped {
+            // Remove the `impl __ {` and `}` lines, and dedent.
+            if let [_impl, ref items @ .., _close] = inner[..] {
+                inner = items.to_vec();
+            }
+        }
+        for line in inner {
+            let line = if wrapped {
+                line.strip_prefix("    ").unwrap_or(line)
+            } else {
+                line
+            };
+            // Usually on a line of their own, but for short blocks.
+            let (line, idxs) = strip_markers(line)?;
+            for &idx in &idxs {
+                table.push((*marker.marked.get(idx)?, lines.len()));
+            }
+            if idxs.is_empty() || line.trim().is_empty().not() {
+                lines.push(line);
+            }
+        }
+        Some((lines.join("\n") + "\n", table))
+    });
+    let (code, table) = formatted.unwrap_or_else(|| {
+        // Fallback to raw output (without the markers).
+        (expansion.to_string() + "\n", vec![])
+    });
+
+    let mut header = vec![
+        if has_locations {
+            format!("// `#[with]` expansion of `{}`, at {}.", name, location(call_site))
+        } else {
+            format!("// `#[with]` expansion of `{}`.", name)
+        },
+        "// Generated by the `expand-macros` feature of `with_locals`.".into(),
+    ];
+    if has_locations.not() {
+        header.push("// (Source locations are only available on Rust 1.88 or later.)".into());
+    }
+    if table.is_empty().not() {
+        header.push("//".into());
+        header.push("// Original statement -> expanded line:".into());
+        // + 1 for the blank line after the header, + 1 since 1-based.
+        let offset = header.len() + table.len() + 1 + 1;
+        header.extend(table.iter().map(|&(start, line)| format!(
+            "//   {} -> {}", location(start), line + offset,
+        )));
+    }
+    let contents = format!("{}\n\n{}", header.join("\n"), code);
+
+    let path = output_path(source_file.as_deref(), name);
+    if let Err(err) =
+        path.parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|()| fs::write(&path, contents))
+    {
+        eprintln!(
+            "warning: `with_locals` failed to write `{}`: {}",
+            path.display(), err,
+        );
+    }
+}
+
+/// Removes the marker statements (`__with_locals_stmt__!(<idx>usize);`) from
+/// `line`, returning their indices; `None` if one of them is not as expected.
+fn strip_markers (line: &'_ str)
+  -> Option<(String, Vec<usize>)>
+{
+    let mut stripped = String::new();
+    let mut idxs = vec![];
+    let mut rest = line;
+    while let Some(pos) = rest.find(MARKER) {
+        stripped.push_str(&rest[.. pos]);
+        // (Within macro invocations, `rustfmt` leaves the tokens spaced out.)
+        let args =
+            rest[pos + MARKER.len() ..]
+                .trim_start().strip_prefix('!')?
+                .trim_start().strip_prefix('(')?
+                .trim_start()
+        ;
+        let digits = args.len() - args.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        idxs.push(args[.. digits].parse().ok()?);
+        rest =
+            args[digits ..]
+                .trim_start_matches("usize")
+                .trim_start().strip_prefix(')')?
+                .trim_start().strip_prefix(';')?
+                .trim_start()
+        ;
+    }
+    stripped.push_str(rest);
+    Some((stripped, idxs))
+}
+
+/// Parses the given item(s) as a `File`, wrapping associated items within a
+/// dummy `impl __ { ... }` block.
+fn as_file (tokens: &'_ TokenStream2)
+  -> File
+{
+    parse2(tokens.clone())
+        .or_else(|_| parse2(quote!( impl __ { #tokens } )))
+        .unwrap_or_else(|_| File { shebang: None, attrs: vec![], items: vec![] })
+}
+
+fn expansion_is_wrapped (expansion: &'_ TokenStream2)
+  -> bool
+{
+    parse2::<File>(expansion.clone()).is_err()
+}
+
+/// Collects the source range of every statement.
+struct StmtRanges<'__> (
+    &'__ mut Vec<(LineColumn, LineColumn)>,
+);
+
+impl VisitMut for StmtRanges<'_> {
+    fn visit_stmt_mut (
+        self: &'_ mut Self,
+        stmt: &'_ mut Stmt,
+    )
+    {
+        let mut tts = stmt.to_token_stream().into_iter();
+        if let Some(first) = tts.next() {
+            let last = tts.last().unwrap_or_else(|| first.clone());
+            let end = match last {
+                | TokenTree::Group(ref group) => group.span_close().end(),
+                | ref tt => tt.span().end(),
+            };
+            self.0.push((first.span().start(), end));
+        }
+        visit_mut::visit_stmt_mut(self, stmt);
+    }
+}
+
+/// Inserts a marker statement before every statement stemming from an
+/// original one.
+///
+/// A statement stems from the innermost original statement containing its
+/// first token with a meaningful span (_i.e._, not a `call_site()` one), if
+/// that original statement has not been accounted for yet.
+struct Marker {
+    stmts: Vec<((LineColumn, LineColumn), bool)>,
+    marked: Vec<LineColumn>,
+    call_site: LineColumn,
+}
+
+fn key (LineColumn { line, column }: LineColumn)
+  -> (usize, usize)
+{
+    (line, column)
+}
+
+impl Marker {
+    fn origin (self: &'_ mut Self, stmt: &'_ Stmt)
+      -> Option<LineColumn>
+    {
+        fn first_located (tts: TokenStream2, call_site: LineColumn)
+          -> Option<LineColumn>
+        {
+            tts.into_iter().find_map(|tt| {
+                let start = tt.span().start();
+                if start.line != 0 && key(start) != key(call_site) {
+                    Some(start)
+                } else if let TokenTree::Group(group) = tt {
+                    first_located(group.stream(), call_site)
+                } else {
+                    None
+                }
+            })
+        }
+        let pos = key(first_located(stmt.to_token_stream(), self.call_site)?);
+        let &mut ((start, _), ref mut seen) =
+            self.stmts
+                .iter_mut()
+                .filter(|((start, end), _)| key(*start) <= pos && pos < key(*end))
+                .min_by_key(|((start, end), _)| {
+                    (end.line - start.line, usize::MAX - start.column)
+                })?
+        ;
+        if mem::replace(seen, true) { None } else { Some(start) }
+    }
+}
+
+impl VisitMut for Marker {
+    fn visit_block_mut (
+        self: &'_ mut Self,
+        block: &'_ mut Block,
+    )
+    {
+        let marker = format_ident!("{}", MARKER);
+        block.stmts = mem::take(&mut block.stmts).into_iter().fold(vec![], |mut acc, mut stmt| {
+            if let Some(start) = self.origin(&stmt) {
+                let idx = self.marked.len();
+                self.marked.push(start);
+                acc.push(parse_quote!( #marker!(#idx); ));
+            }
+            self.visit_stmt_mut(&mut stmt);
+            acc.push(stmt);
+            acc
+        });
+    }
+}
+
+/// The source file of the item being expanded, relative to the crate root
+/// (Rust 1.88 or later).
+fn source_file ()
+  -> Option<PathBuf>
+{
+    let file = fs::canonicalize(Span::call_site().local_file()?).ok()?;
+    let manifest_dir = fs::canonicalize(env::var_os("CARGO_MANIFEST_DIR")?).ok()?;
+    file.strip_prefix(manifest_dir).ok().map(Path::to_owned)
+}
+
+/// `<dir>/<crate>/<module>::<name>.rs`, where `<module>` is derived from the
+/// path of the source file, relative to the crate root, and with a `.<n>`
+/// suffix for the `n`-th item of the crate ending up with that file name.
+fn output_path (source_file: Option<&'_ Path>, name: &'_ str)
+  -> PathBuf
+{
+    let dir = env::var_os("WITH_LOCALS_EXPAND_DIR").map_or_else(
+        || target_dir().join("with_locals"),
+        PathBuf::from,
+    );
+    let krate =
+        env::var("CARGO_CRATE_NAME")
+            .or_else(|_| env::var("CARGO_PKG_NAME"))
+            .unwrap_or_else(|_| "unknown".into())
+    ;
+    let mut module: Vec<String> =
+        source_file
+            .map(|path| path.with_extension(""))
+            .iter()
+            .flat_map(|path| path.components().skip(1))
+            .map(|component| component.as_os_str().to_string_lossy().into_owned())
+            .collect()
+    ;
+    // `src/lib.rs`, `src/main.rs`, `src/bin/foo.rs`, `tests/foo.rs`, _etc._
+    // are crate roots.
+    if module.len() == 1 || module.first().map(|it| &**it) == Some("bin") {
+        module.clear();
+    }
+    if module.last().map(|it| &**it) == Some("mod") {
+        module.pop();
+    }
+    // `impl Foo < T >` becomes `impl_Foo_T_`.
+    let is_ident_char = |c: char| c.is_alphanumeric() || c == '_';
+    let mut file_name = String::new();
+    let mut prev_word = "";
+    for word in name.split_whitespace() {
+        if prev_word.ends_with(is_ident_char) && word.starts_with(is_ident_char) {
+            file_name.push('_');
+        }
+        file_name.extend(word.chars().map(|c| if is_ident_char(c) { c } else { '_' }));
+        prev_word = word;
+    }
+    module.push(file_name);
+    let stem = module.join(MODULE_SEPARATOR);
+    WRITTEN.with(|written| {
+        let mut written = written.borrow_mut();
+        let path =
+            (1 ..)
+                .map(|n| dir.join(&krate).join(match n {
+                    | 1 => format!("{}.rs", stem),
+                    | _ => format!("{}.{}.rs", stem, n),
+                }))
+                .find(|path| written.contains(path).not())
+                .unwrap()
+        ;
+        written.push(path.clone());
+        path
+    })
+}
+
+/// `$CARGO_TARGET_DIR`, or else the closest `target` directory of the
+/// manifest dir ancestors (for workspaces), or else `$CARGO_MANIFEST_DIR/target`.
+fn target_dir ()
+  -> PathBuf
+{
+    if let Some(dir) = env::var_os("CARGO_TARGET_DIR") {
+        return dir.into();
+    }
+    let manifest_dir = PathBuf::from(env::var_os("CARGO_MANIFEST_DIR").unwrap_or_default());
+    manifest_dir
+        .ancestors()
+        .map(|dir| dir.join("target"))
+        .find(|dir| dir.is_dir())
+        .unwrap_or_else(|| manifest_dir.join("target"))
+}
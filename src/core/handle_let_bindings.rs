@@ -0,0 +1,830 @@
+use super::*;
+
+pub(in super) use handle_let_bindings as f;
+
+/// ```rust,ignore
+/// #[with] let var = func(/* args */);
+/// ...
+/// ```
+///
+/// must become:
+///
+/// ```rust,ignore
+/// func(/* args */, |var| {
+///     ...
+/// })
+/// ```
+pub(in super)
+fn handle_let_bindings (
+    block: &'_ mut Block,
+    &Attrs { ref lifetime, dyn_safe, recursive, .. }: &'_ Attrs,
+) -> Result<()>
+{
+    let mut encountered_error = None;
+    // The called functions are named after the crate-wide pattern, regardless
+    // of the name of the enscoping function: this keeps the call sites in sync
+    // with the definitions of the crate, at the cost of calls to with-functions
+    // of other crates (named after another pattern, such as the default one)
+    // having to spell it (`#[with(name = "with_{}")] let ...`).
+    let config = config::config();
+    let mut visitor = ReplaceLetBindingsWithCbCalls {
+        encountered_error: &mut encountered_error,
+        lifetime: &*lifetime,
+        name: &config.name,
+        dyn_safe_calls: if recursive { dyn_safe } else { DynSafe::No },
+    };
+    visitor.visit_block_mut(block);
+    encountered_error.map_or(Ok(()), Err)
+}
+
+struct ReplaceLetBindingsWithCbCalls<'__> {
+    encountered_error: &'__ mut Option<::syn::Error>,
+    lifetime: &'__ str,
+    /// The (crate-wide) name pattern of the called with-functions, unless
+    /// overridden at the call site.
+    name: &'__ str,
+    dyn_safe_calls: DynSafe,
+}
+
+/// The with-specific parts of a `#[with] let <binding> = <call>;` statement.
+struct WithLet {
+    binding: Pat,
+
+    /// The `with_`-prefixed call, still lacking its continuation argument.
+    call: Expr,
+
+    /// Statements to prepend to the continuation body (_e.g._, for
+    /// `let x = f()?;`, `call` is `with_f()`, whose continuation starts with
+    /// `let x = __with_locals_anon__?;`).
+    prelude: Vec<Stmt>,
+
+    /// The other attributes of the `let` statement (_e.g._, `#[cfg(...)]` or
+    /// `#[allow(...)]`).
+    attrs: Vec<Attribute>,
+}
+
+impl ReplaceLetBindingsWithCbCalls<'_> {
+    /// Finds the first `( #[with] )? let <binding> (: <ty>)? = <expr>;`
+    /// statement, removing its `#[with]` attribute.
+    ///
+    /// Returns its index, and its call-site arguments (_e.g._, whether the
+    /// continuation is to be a `move` one).
+    fn find_with_let (
+        self: &'_ mut Self,
+        stmts: &'_ mut [Stmt],
+    ) -> Option<(usize, CallSiteAttrs)>
+    {
+        let mut ret = CallSiteAttrs::default();
+        let idx = (0 ..).zip(stmts).find_map(|(i, stmt)| {
+            if let Stmt::Local(ref mut let_binding) = *stmt {
+                let mut has_with = false;
+                let mut errors = None;
+                let_binding.attrs.retain(|attr| {
+                    if attr.path.is_ident("with") {
+                        has_with = true;
+                        match parse2::<CallSiteAttrs>(attr.tokens.clone()) {
+                            | Ok(call_site_attrs) => {
+                                if let Some(dyn_safe) = call_site_attrs.dyn_safe {
+                                    self.dyn_safe_calls = dyn_safe;
+                                }
+                                ret = call_site_attrs;
+                            },
+                            | Err(err) => errors.push_error(err),
+                        }
+                        false // remove attr
+                    } else if cfg_attr_applies(attr, "with") {
+                        errors.push_error(Error::new_spanned(attr, "\
+                            `#[with]` cannot be applied through `#[cfg_attr]`: \
+                            use a `#[cfg(...)]` `#[with] let` (and a \
+                            `#[cfg(not(...))]` `let`) instead.\
+                        "));
+                        false
+                    } else {
+                        true
+                    }
+                });
+                // Also look for a special lifetime
+                has_with |= {
+                    let ref mut lifetimes = vec![];
+                    LifetimeVisitor { lifetimes, lifetime: self.lifetime }
+                        .visit_pat_mut(&mut let_binding.pat)
+                    ;
+                    lifetimes.is_empty().not()
+                };
+                if has_with {
+                    // The `#[cfg]`s of a `#[with] let` need to be seen (see
+                    // `cps_transform`).
+                    let_binding.attrs.retain(|attr| if cfg_attr_applies(attr, "cfg") {
+                        errors.push_error(Error::new_spanned(attr, "\
+                            `#[cfg]` cannot be applied to a `#[with] let` \
+                            through `#[cfg_attr]`: use `#[cfg(all(...))]` \
+                            instead.\
+                        "));
+                        false
+                    } else {
+                        true
+                    });
+                }
+                if let Some(err) = errors {
+                    self.encountered_error.push_error(err);
+                }
+                if has_with {
+                    return Some(i);
+                }
+            }
+            None
+        })?;
+        Some((idx, ret))
+    }
+
+    /// Extracts the binding and the `with_`-prefixed call out of a
+    /// `#[with] let` statement.
+    fn parse_with_let (
+        let_assign: Local,
+        name: &'_ str,
+    ) -> Result<WithLet>
+    {
+        let let_attrs = let_assign.attrs;
+        let mut binding = let_assign.pat;
+        let init =
+            if let Some(it) = let_assign.init { it } else {
+                return Err(Error::new(let_assign.semi_token.span(),
+                    "Missing expression",
+                ));
+            }
+        ;
+        let mut prelude = vec![];
+        let mut call = *init.1;
+        let func = loop {
+            break match call {
+                | Expr::MethodCall(ExprMethodCall {
+                    ref mut method,
+                    ..
+                })
+                => {
+                    method
+                },
+
+                | Expr::Call(ExprCall {
+                    ref mut func,
+                    ..
+                }) => {
+                    let path = match **func {
+                        | Expr::Path(ref mut it) => it,
+                        | _ => return Err(Error::new(func.span(),
+                            "Expected a function name",
+                        )),
+                    };
+                    let at_last /* pun intended */ =
+                        path.path
+                            .segments
+                            .iter_mut()
+                            .next_back()
+                            .unwrap()
+                    ;
+                    // (Its turbofish, if any, is completed by `cps_transform`.)
+                    &mut at_last.ident
+                },
+
+                | Expr::Match(ExprMatch {
+                    ref mut expr,
+                    match_token: token::Match {
+                        span,
+                    },
+                    ..
+                })
+                | Expr::Try(ExprTry {
+                    ref mut expr,
+                    question_token: token::Question {
+                        spans: [span],
+                    },
+                    ..
+                }) => {
+                    let anon = format_ident!(
+                        "__with_locals_anon__",
+                        span = span,
+                    );
+                    let expr = mem::replace(expr, parse_quote! {
+                        #anon
+                    });
+                    prelude.push(parse_quote! {
+                        let #binding = #call;
+                    });
+                    binding = parse_quote!( #anon );
+                    call = *expr;
+                    continue;
+                },
+
+                | ref extraneous => return Err(Error::new(extraneous.span(),
+                    "\
+                        expected \
+                        `function(...)`, \
+                        `function(...)?...?`, \
+                        or `<expr>.method(...)`, \
+                        or `<expr>.method(...)?...?`\
+                    ",
+                )),
+            }
+        };
+
+        // func: prepend `with_` to the function name (by default)
+        *func = with_ident(name, func);
+
+        // (The attributes of the call expression, if any, are kept on it.)
+        Ok(WithLet { binding, call, prelude, attrs: let_attrs })
+    }
+
+    /// Performs the CPS transformation (see [`handle_let_bindings`]) of the
+    /// (valid) `#[with] let` statement at index `i`.
+    fn cps_transform (
+        self: &'_ mut Self,
+        block: &'_ mut Block,
+        i: usize,
+        WithLet { binding, mut call, mut prelude, attrs }: WithLet,
+        call_site_attrs: &'_ CallSiteAttrs,
+        orig_dyn_safe_calls: DynSafe,
+    )
+    {
+        let mut stmts_after_with_let: ::std::collections::VecDeque<_> =
+            block
+                .stmts
+                // .split_off(i + 1)
+                .drain((i + 1) ..).collect()
+        ;
+        block.stmts.pop(); // the `let` statement.
+        let (cfgs, attrs): (Vec<_>, Vec<_>) =
+            attrs.into_iter().partition(|attr| attr.path.is_ident("cfg"))
+        ;
+        // The remaining attributes (_e.g._, lints) apply to the binding alone,
+        // that is, to the `let` of the prelude introducing it, if any, or else
+        // to one introducing it at the start of the continuation (a closure
+        // parameter only accepting a few kinds of attributes).
+        let binding: Pat = match (attrs.is_empty(), prelude.first_mut()) {
+            | (true, _) => binding,
+            | (false, Some(&mut Stmt::Local(ref mut binding_let))) => {
+                binding_let.attrs.extend(attrs);
+                binding
+            },
+            | (false, _) => {
+                let span = binding.span();
+                let arg = format_ident!("__with_locals_binding__", span = span);
+                let (pat, ty) = match binding {
+                    | Pat::Type(PatType { pat, ty, .. }) => (*pat, Some(ty)),
+                    | pat => (pat, None),
+                };
+                stmts_after_with_let.push_front(parse_quote_spanned! {span=>
+                    #(#cfgs)*
+                    #(#attrs)*
+                    let #pat = #arg;
+                });
+                match ty {
+                    | Some(ty) => parse_quote_spanned!(span=> #arg: #ty),
+                    | None => parse_quote_spanned!(span=> #arg),
+                }
+            },
+        };
+        // The prelude is only valid alongside the continuation parameter it
+        // binds from.
+        for stmt in &mut prelude {
+            if let Stmt::Local(ref mut local) = *stmt {
+                local.attrs.splice(0 .. 0, cfgs.iter().cloned());
+            }
+        }
+        for stmt in prelude {
+            stmts_after_with_let.push_front(stmt);
+        }
+
+        let wrap_statements_inside_closure_body::Ret {
+            closure_body,
+            ty: continuation_ret,
+            wrap_ret,
+            wrap_break,
+            wrap_continue } =
+                wrap_statements_inside_closure_body::f(
+                    stmts_after_with_let,
+                    call_site_attrs,
+                    self.encountered_error,
+                )
+        ;
+
+        // The continuation and its handling point at the call.
+        let call_span = call.span();
+        let span = spanned_at(call_span);
+        proc_macro_use! {
+            #[spanned(call_span)]
+            use $krate::{ControlFlow};
+        }
+
+        let move_ = if call_site_attrs.move_ {
+            quote_spanned!(span=> move )
+        } else {
+            quote!()
+        };
+
+        // turbofish, if any: append the `ContinuationRet` and `Continuation`
+        // type parameters.
+        let continuation_ret = if self.dyn_safe_calls.is_dyn_safe() {
+            quote!( _ )
+        } else {
+            continuation_ret
+        };
+        match call {
+            | Expr::MethodCall(ExprMethodCall {
+                turbofish: Some(ref mut turbofish),
+                ..
+            }) => {
+                turbofish.args.push(parse_quote!( #continuation_ret ));
+                turbofish.args.push(parse_quote!( _ ));
+            },
+            | Expr::Call(ExprCall { ref mut func, .. }) => {
+                if let Expr::Path(ExprPath { ref mut path, .. }) = **func {
+                    let at_last = path.segments.iter_mut().next_back().unwrap();
+                    if let PathArguments::AngleBracketed(ref mut turbofish) =
+                        at_last.arguments
+                    {
+                        turbofish.args.push(parse_quote!( #continuation_ret ));
+                        turbofish.args.push(parse_quote!( _ ));
+                    }
+                }
+            },
+            | _ => {},
+        }
+
+        // With `#[cfg]`s, the continuation is also used when these are not
+        // met, by being called directly (the binding, its parameter, being
+        // `#[cfg]`-ed out as well), since the statements that follow the
+        // `#[with] let` are then no longer nested. Rather than repeating it
+        // (which, for `N` such nested `#[with] let`s, would lead to `2^N`
+        // copies of the innermost statements), it is defined once, as a local
+        // macro, and only its calls are `#[cfg]`-selected:
+        //
+        // ```rust,ignore
+        // macro_rules! __with_locals_continuation__ {() => (
+        //     |#[cfg(...)] var| { ... }
+        // )}
+        // #[cfg(...)]
+        // let __with_locals_ret__ = func(/* args */, __with_locals_continuation__!());
+        // #[cfg(not(all(...)))]
+        // let __with_locals_ret__ = (__with_locals_continuation__!())();
+        // __with_locals_ret__
+        // ```
+        let mut continuation: Expr = parse_quote_spanned!(span=>
+            #move_ |#(#cfgs)* #binding| #closure_body
+        );
+        let (continuation, continuation_macro) = if cfgs.is_empty() {
+            (continuation, None)
+        } else {
+            // Macro definitions are not visited, so the nested `#[with] let`s
+            // have to be handled now.
+            let call_site_dyn_safe_calls =
+                mem::replace(&mut self.dyn_safe_calls, orig_dyn_safe_calls)
+            ;
+            self.visit_expr_mut(&mut continuation);
+            self.dyn_safe_calls = call_site_dyn_safe_calls;
+            let continuation_macro: Stmt = parse_quote_spanned!(span=>
+                macro_rules! __with_locals_continuation__ {() => (
+                    #continuation
+                )}
+            );
+            (
+                parse_quote_spanned!(span=> __with_locals_continuation__!()),
+                Some(continuation_macro),
+            )
+        };
+
+        // args: append the continuation
+        let args = match call {
+            | Expr::MethodCall(ExprMethodCall { ref mut args, .. })
+            | Expr::Call(ExprCall { ref mut args, .. })
+            => args,
+            | _ => unreachable!(),
+        };
+        match self.dyn_safe_calls {
+            | DynSafe::No => args.push(continuation.clone()),
+            | DynSafe::FnMut => {
+                proc_macro_use! {
+                    #[spanned(call_span)]
+                    use $krate::{Slot};
+                }
+                args.push(parse_quote_spanned!(span=>
+                    &mut |__ret__| __with_locals_slot__.feed(__ret__)
+                ));
+                call = parse_quote_spanned!(span=> {
+                    let mut __with_locals_slot__ = #Slot::new(#continuation);
+                    let _ = #call;
+                    __with_locals_slot__.into_inner()
+                });
+            },
+            // A genuine `FnOnce`, directly called by the with-function (no
+            // `Slot` involved), but for its return value, which is stashed
+            // in a local, since the boxed continuation returns a
+            // `ContinuationReturn`.
+            | DynSafe::Boxed => {
+                proc_macro_use! {
+                    #[spanned(call_span)]
+                    use $krate::{Box, ContinuationReturn, None_, Some_};
+                }
+                args.push(parse_quote_spanned!(span=>
+                    #Box::new(#move_ |__with_locals_arg__| {
+                        *__with_locals_ret_mut__ =
+                            #Some_((#continuation)(__with_locals_arg__))
+                        ;
+                        #ContinuationReturn
+                    })
+                ));
+                call = parse_quote_spanned!(span=> {
+                    let mut __with_locals_ret__ = #None_;
+                    let __with_locals_ret_mut__ = &mut __with_locals_ret__;
+                    let #ContinuationReturn = #call;
+                    __with_locals_ret__.expect("\
+                        Fatal `with_locals` error: \
+                        failed to call the continuation.\
+                    ")
+                });
+            },
+        }
+        let nested_call = |call: &'_ Expr| -> Expr { parse_quote_spanned! {span=>
+            match #call {
+                | #ControlFlow::Eval(it) => it,
+                | #ControlFlow::EarlyReturn(it) => #wrap_ret,
+                | #ControlFlow::Break(it) => #wrap_break,
+                | #ControlFlow::Continue(it) => #wrap_continue,
+            }
+        }};
+        if cfgs.is_empty() {
+            block.stmts.push(Stmt::Expr(nested_call(&call)));
+            return;
+        }
+        block.stmts.extend(continuation_macro);
+        let with_call = nested_call(&call);
+        block.stmts.push(parse_quote_spanned! {span=>
+            #(#cfgs)*
+            let __with_locals_ret__ = #with_call;
+        });
+        let predicates = cfgs.iter().map(|cfg| cfg.parse_args::<TokenStream2>());
+        let predicates =
+            predicates.collect::<Result<Vec<_>>>().unwrap_or_else(|err| {
+                self.encountered_error.push_error(err);
+                vec![]
+            })
+        ;
+        let direct_call = nested_call(&parse_quote_spanned!(span=>
+            (#continuation)()
+        ));
+        block.stmts.push(parse_quote_spanned! {span=>
+            #[cfg(not(all(#(#predicates),*)))]
+            let __with_locals_ret__ = #direct_call;
+        });
+        block.stmts.push(Stmt::Expr(parse_quote_spanned! {span=>
+            __with_locals_ret__
+        }));
+    }
+}
+
+impl VisitMut for ReplaceLetBindingsWithCbCalls<'_> {
+    fn visit_item_mut (
+        self: &'_ mut Self,
+        _: &'_ mut Item,
+    )
+    {
+        // Do not recurse into items defined inside the function body.
+    }
+
+    fn visit_block_mut (
+        self: &'_ mut Self,
+        block: &'_ mut Block,
+    )
+    {
+        self.hoist_with_macros(block);
+        let orig_dyn_safe_calls = self.dyn_safe_calls;
+        let mut search_start = 0;
+        while let Some((i, call_site_attrs)) =
+            self.find_with_let(&mut block.stmts[search_start ..])
+        {
+            let name = call_site_attrs.name.as_deref().unwrap_or(self.name);
+            let i = search_start + i;
+            let let_assign = match block.stmts[i] {
+                | Stmt::Local(ref it) => it.clone(),
+                | _ => unreachable!(),
+            };
+            match Self::parse_with_let(let_assign, name) {
+                | Ok(with_let) => {
+                    self.cps_transform(
+                        block,
+                        i,
+                        with_let,
+                        &call_site_attrs,
+                        orig_dyn_safe_calls,
+                    );
+                    break;
+                },
+                | Err(err) => {
+                    // Leave the statement as is, and look for the next one.
+                    self.encountered_error.push_error(err);
+                    self.dyn_safe_calls = orig_dyn_safe_calls;
+                    search_start = i + 1;
+                },
+            }
+        }
+        self.dyn_safe_calls = orig_dyn_safe_calls;
+        // sub-recurse.
+        block
+            .stmts
+            .iter_mut()
+            .for_each(|stmt| self.visit_stmt_mut(stmt))
+        ;
+    }
+
+    /// The following functions are not necessary, but they lead to nicer
+    /// error messages if the `#[with]` attribute is misplaced.
+    ///
+    /// Indeed, imagine someone annotating an assignment instead of a
+    /// new `let` binding. In that case, the previous visitor will not
+    /// catch it, thus leaving the attribute as is, which not only makes no
+    /// sense, it can also trigger things such as:
+    ///
+    /// ```text
+    /// error[E0658]: attributes on expressions are experimental
+    /// ```
+    ///
+    /// These visitors will try to catch that, and provide a nicer error
+    /// message (removing the attribute, so that it is the only one).
+    fn visit_expr_mut (
+        self: &'_ mut Self,
+        expr: &'_ mut Expr,
+    )
+    {
+        if let Some(attrs) = expr_attrs_mut(expr) {
+            self.remove_misplaced_with_attrs(attrs);
+        }
+        if let Expr::Macro(ExprMacro { ref mac, .. }) = *expr {
+            if mac.path.is_ident("with") {
+                // Not hoisted (_e.g._, outside of any block): see
+                // `hoist_with_macros`.
+                self.encountered_error.push_error(Error::new(
+                    mac.span(),
+                    "`with!(...)` must be used within a statement of a block",
+                ));
+                if let Ok(call) = mac.parse_body::<Expr>() {
+                    *expr = call;
+                }
+            }
+        }
+        visit_mut::visit_expr_mut(self, expr);
+    }
+
+    fn visit_stmt_mut (
+        self: &'_ mut Self,
+        stmt: &'_ mut Stmt,
+    )
+    {
+        if let Stmt::Item(Item::Macro(ref mut mac)) = *stmt {
+            self.remove_misplaced_with_attrs(&mut mac.attrs);
+        }
+        visit_mut::visit_stmt_mut(self, stmt);
+    }
+}
+
+impl ReplaceLetBindingsWithCbCalls<'_> {
+    /// ```rust,ignore
+    /// foo(with!(hex(1)), with!(hex(2)));
+    /// ...
+    /// ```
+    ///
+    /// becomes:
+    ///
+    /// ```rust,ignore
+    /// #[with] let __with_locals_hoisted_0__ = hex(1);
+    /// #[with] let __with_locals_hoisted_1__ = hex(2);
+    /// foo(__with_locals_hoisted_0__, __with_locals_hoisted_1__);
+    /// ...
+    /// ```
+    ///
+    /// Hence the `with!` calls being evaluated before the other sub-expressions
+    /// of the statement, even those which precede them (_e.g._, in
+    /// `foo(bar(), with!(hex(1)))`, `hex(1)` is called before `bar()`).
+    ///
+    /// The `with!` calls within a conditionally evaluated (or re-evaluated)
+    /// expression, such as a `match` arm, are hoisted within a block wrapping
+    /// that expression instead.
+    ///
+    /// The arguments of the macros known to take expressions (such as
+    /// `println!` or `assert!`: see `visit_macro_args_mut`) are handled too.
+    fn hoist_with_macros (
+        self: &'_ mut Self,
+        block: &'_ mut Block,
+    )
+    {
+        struct Hoister<'__> {
+            encountered_error: &'__ mut Option<Error>,
+            hoisted: Vec<Stmt>,
+            /// To name the hoisted bindings.
+            count: usize,
+        }
+        impl Hoister<'_> {
+            fn hoist (
+                self: &'_ mut Self,
+                mac: &'_ Macro,
+                binding: Pat,
+            )
+            {
+                let mut call: Expr = match mac.parse_body() {
+                    | Ok(it) => it,
+                    | Err(err) => {
+                        self.encountered_error.push_error(err);
+                        parse_quote!( () )
+                    },
+                };
+                // Nested `with!`s are evaluated first.
+                self.visit_expr_mut(&mut call);
+                let span = mac.span();
+                self.hoisted.push(parse_quote_spanned! {span=>
+                    #[with]
+                    let #binding = #call;
+                });
+            }
+
+            /// `expr` becomes `{ <hoisted statements> expr }`, if need be.
+            fn hoist_within (
+                self: &'_ mut Self,
+                expr: &'_ mut Expr,
+            )
+            {
+                let mut hoister = Hoister {
+                    encountered_error: &mut *self.encountered_error,
+                    hoisted: vec![],
+                    count: self.count,
+                };
+                hoister.visit_expr_mut(expr);
+                self.count = hoister.count;
+                let hoisted = hoister.hoisted;
+                if hoisted.is_empty().not() {
+                    *expr = parse_quote!({
+                        #(#hoisted)*
+                        #expr
+                    });
+                }
+            }
+        }
+        impl VisitMut for Hoister<'_> {
+            fn visit_item_mut (
+                self: &'_ mut Self,
+                item: &'_ mut Item,
+            )
+            {
+                // Stop recursing (but for statement macros).
+                if let Item::Macro(ItemMacro { ident: None, ref mut mac, .. }) = *item {
+                    self.visit_macro_mut(mac);
+                }
+            }
+
+            fn visit_macro_mut (
+                self: &'_ mut Self,
+                mac: &'_ mut Macro,
+            )
+            {
+                visit_macro_args_mut(self, mac);
+            }
+
+            fn visit_expr_mut (
+                self: &'_ mut Self,
+                expr: &'_ mut Expr,
+            )
+            {
+                match *expr {
+                    | Expr::Macro(ExprMacro { ref mac, .. })
+                        if mac.path.is_ident("with")
+                    => {
+                        let hoisted = format_ident!(
+                            "__with_locals_hoisted_{}__", self.count,
+                            span = mac.span(),
+                        );
+                        self.count += 1;
+                        self.hoist(mac, parse_quote!( #hoisted ));
+                        *expr = parse_quote!( #hoisted );
+                    },
+
+                    // Conditionally evaluated (or re-evaluated): hoisted
+                    // within their own block.
+                    | Expr::If(ExprIf {
+                        ref mut cond,
+                        ref mut else_branch,
+                        ..
+                    }) => {
+                        self.visit_expr_mut(cond);
+                        if let Some((_, ref mut else_if)) = *else_branch {
+                            self.hoist_within(else_if);
+                        }
+                    },
+                    | Expr::Match(ExprMatch {
+                        ref mut expr,
+                        ref mut arms,
+                        ..
+                    }) => {
+                        self.visit_expr_mut(expr);
+                        arms.iter_mut().for_each(|arm| {
+                            if let Some((_, ref mut guard)) = arm.guard {
+                                self.hoist_within(guard);
+                            }
+                            self.hoist_within(&mut arm.body);
+                        });
+                    },
+                    | Expr::Binary(ExprBinary {
+                        ref mut left,
+                        op: BinOp::And(_) | BinOp::Or(_),
+                        ref mut right,
+                        ..
+                    }) => {
+                        self.visit_expr_mut(left);
+                        self.hoist_within(right);
+                    },
+                    | Expr::Closure(ExprClosure { body: ref mut it, .. })
+                    | Expr::While(ExprWhile { cond: ref mut it, .. })
+                    => {
+                        self.hoist_within(it);
+                    },
+                    | Expr::ForLoop(ExprForLoop { ref mut expr, .. }) => {
+                        self.visit_expr_mut(expr);
+                    },
+
+                    // With their own statements, where the hoisting happens.
+                    | Expr::Async(_)
+                    | Expr::Block(_)
+                    | Expr::Loop(_)
+                    | Expr::TryBlock(_)
+                    | Expr::Unsafe(_)
+                    => {},
+
+                    | _ => visit_mut::visit_expr_mut(self, expr),
+                }
+            }
+        }
+
+        let stmts = mem::take(&mut block.stmts);
+        block.stmts.reserve(stmts.len());
+        for mut stmt in stmts {
+            let mut hoister = Hoister {
+                encountered_error: &mut *self.encountered_error,
+                hoisted: vec![],
+                count: 0,
+            };
+            match stmt {
+                // `with!(...);` on its own.
+                | Stmt::Item(Item::Macro(ItemMacro { ref mac, ident: None, .. }))
+                | Stmt::Semi(Expr::Macro(ExprMacro { ref mac, .. }), _)
+                    if mac.path.is_ident("with")
+                => {
+                    hoister.hoist(mac, parse_quote!( _ ));
+                    block.stmts.extend(hoister.hoisted);
+                    continue;
+                },
+                | _ => hoister.visit_stmt_mut(&mut stmt),
+            }
+            block.stmts.extend(hoister.hoisted);
+            block.stmts.push(stmt);
+        }
+    }
+
+    fn remove_misplaced_with_attrs (
+        self: &'_ mut Self,
+        attrs: &'_ mut Vec<Attribute>,
+    )
+    {
+        let encountered_error = &mut *self.encountered_error;
+        attrs.retain(|attr| if attr.path.is_ident("with") {
+            encountered_error.push_error(Error::new(
+                attr.span(),
+                "`#[with]` must be applied to a `let` binding.",
+            ));
+            false
+        } else {
+            true
+        });
+    }
+}
+
+/// Whether `attr` is a `#[cfg_attr(<predicate>, <attrs>...)]` one applying a
+/// `#[<name>]` (or `#[<name>(...)]`) attribute.
+fn cfg_attr_applies (attr: &'_ Attribute, name: &'_ str)
+  -> bool
+{
+    if attr.path.is_ident("cfg_attr").not() {
+        return false;
+    }
+    let applied_attrs = |input: ParseStream<'_>| {
+        let _predicate: NestedMeta = input.parse()?;
+        let mut paths = vec![];
+        while input.parse::<Option<Token![,]>>()?.is_some() && input.is_empty().not() {
+            paths.push(Path::parse_mod_style(input)?);
+            // Skip its arguments, if any.
+            while input.is_empty().not() && input.peek(Token![,]).not() {
+                input.parse::<::proc_macro2::TokenTree>()?;
+            }
+        }
+        Ok(paths)
+    };
+    matches!(
+        attr.parse_args_with(applied_attrs),
+        Ok(paths) if paths.iter().any(|path| path.is_ident(name))
+    )
+}
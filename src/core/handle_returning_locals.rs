@@ -0,0 +1,651 @@
+fn handle_returning_locals (
+    fun: &'_ mut impl helpers::FnLike,
+    with_attrs: &'_ Attrs,
+    outer_scope: Option<(&'_ Generics, ::func_wrap::ImplOrTrait<'_>)>,
+) -> Result<()>
+{
+    // Errors are reported alongside a best-effort transformation.
+    let mut encountered_error = None;
+    let     &Attrs {
+        ref lifetime,
+        ref continuation,
+        auto_return,
+        dyn_safe,
+        recursive,
+        ref continuation_bounds,
+        ref name,
+        rename: _, // Checked by the enscoping `impl`, `trait` or `mod`, if any.
+        krate: _, macros: _, // Handled by `handle_fn_like`.
+            } = with_attrs
+    ;
+    let not_dyn_safe = dyn_safe.is_dyn_safe().not();
+    // Note: currently, the necessary `dyn`-safe transformations also allow
+    // preventing the recursive function issue, so no need to apply any extra
+    // transformations.
+    let mut recursive = recursive && not_dyn_safe;
+    let continuation_name =
+        if let Some(ref continuation_name) = continuation {
+            format_ident!("{}", continuation_name)
+        } else {
+            format_ident!("__continuation__")
+        }
+    ;
+    let fun = fun.fields();
+    let ret_ty =
+        if let ReturnType::Type(_, ref mut it) = fun.sig.output { it } else {
+            // Nothing to do
+            return Ok(());
+        }
+    ;
+    let mut lifetimes = vec![]; {
+        LifetimeVisitor { lifetimes: &mut lifetimes, lifetime: &*lifetime }
+            .visit_type_mut(ret_ty)
+        ;
+    }
+    if lifetimes.is_empty() {
+        // Nothing to do
+        return Ok(());
+    }
+
+    // By now, there is at least one `'self` occurence in the return type:
+    // transform the whole function into one using the `with_` continuation
+    // pattern.
+    let __ { sig, block, .. } = fun;
+    let &mut Signature {
+        ref mut ident,
+        ref mut inputs,
+        ref mut output,
+        ref mut generics, .. } = sig;
+    // Add the <R, F : FnOnce(OutputReferringToLocals) -> R> generic params.
+    // (Or use the `dyn`-safe equivalent).
+    // Extra bounds (_e.g._, `Send`) apply to both the continuation and the
+    // value it returns, since the latter is produced wherever the former runs.
+    let (bounds, dyn_lifetime) = if continuation_bounds.is_empty() {
+        (quote!(), quote!('_ +))
+    } else {
+        let has_lifetime =
+            continuation_bounds
+                .iter()
+                .any(|bound| matches!(bound, TypeParamBound::Lifetime(_)))
+        ;
+        if has_lifetime && recursive {
+            encountered_error.push_error(Error::new_spanned(continuation_bounds, "\
+                lifetime bounds on the continuation are not compatible with \
+                `recursive = true`\
+            "));
+            recursive = false;
+        }
+        if has_lifetime && dyn_safe.is_dyn_safe() {
+            // The call sites hand a continuation borrowing their local state.
+            encountered_error.push_error(Error::new_spanned(continuation_bounds, "\
+                lifetime bounds on the continuation are not compatible with \
+                `dyn_safe`\
+            "));
+        }
+        (
+            quote!( + #continuation_bounds ),
+            // `dyn` types can only feature one lifetime bound.
+            if has_lifetime { quote!() } else { quote!('_ +) },
+        )
+    };
+    let R = if not_dyn_safe {
+        let new_ty_param = quote!(
+            __Continuation_Return__
+        );
+        generics.params.push(if continuation_bounds.is_empty() {
+            parse_quote!( #new_ty_param )
+        } else {
+            parse_quote!( #new_ty_param : #continuation_bounds )
+        });
+        new_ty_param
+    } else {
+        let crate_path = helpers::crate_path(Span::call_site());
+        quote!(
+            #crate_path::dyn_safe::ContinuationReturn
+        )
+    };
+    let ret =
+        match ::core::mem::replace(output, parse_quote!( -> #R )) {
+            | ReturnType::Type(_, ty) => *ty,
+            | ReturnType::Default => unreachable!(),
+        }
+    ;
+    proc_macro_use! {
+        use $krate::{FnMut, FnOnce};
+    }
+    let F = match dyn_safe {
+        | DynSafe::No => {
+            let new_ty_param = quote!(
+                __Continuation__
+            );
+            generics.params.push(parse_quote! {
+                #new_ty_param
+                :
+                // for<#(#lifetimes),*>
+                #FnOnce(#ret) -> #R #bounds
+            });
+            new_ty_param
+        },
+        | DynSafe::FnMut => quote!(
+            &'_ mut (dyn #dyn_lifetime #FnMut(#ret) -> #R #bounds)
+        ),
+        | DynSafe::Boxed => {
+            proc_macro_use! {
+                use $krate::{Box};
+            }
+            quote!(
+                #Box<dyn #dyn_lifetime #FnOnce(#ret) -> #R #bounds>
+            )
+        },
+    };
+    inputs.push(parse_quote!(
+        #continuation_name : #F
+    ));
+    let new_ident = with_ident(name, ident);
+    if new_ident == *ident {
+        encountered_error.push_error(Error::new(ident.span(), "\
+            the generated function must not have the name of the original one\
+        "));
+    }
+    *ident = new_ident;
+    let auto_return = match (auto_return, continuation) {
+        | (Some((false, span)), &None) => {
+            // There would be no way to return from the function.
+            encountered_error.push_error(Error::new(span, "\
+                `auto_return = false` requires a `continuation_name = ...` \
+                through which to call the continuation\
+            "));
+            true
+        },
+        | (Some((auto_return, _)), _) => auto_return,
+        | (None, _) => continuation.is_none(),
+    };
+    if let Some(block) = block {
+        // Only apply `return <expr> -> return cont(<expr>)` magic
+        // if no continuation name has been provided (unless explicitly
+        // requested with `auto_return`).
+        if auto_return {
+            // Replace any terminating `expr` with `return <expr>`:
+            #[derive(Default)]
+            struct AddExplicitReturns {
+                done: bool,
+            }
+            impl VisitMut for AddExplicitReturns {
+                fn visit_item_mut (
+                    self: &'_ mut Self,
+                    _: &'_ mut Item,
+                )
+                {
+                    // Stop recursing.
+                }
+
+                fn visit_block_mut (
+                    self: &'_ mut Self,
+                    block: &'_ mut Block,
+                )
+                {
+                    match block.stmts.last_mut() {
+                        | Some(&mut Stmt::Expr(ref mut expr)) => {
+                            self.visit_expr_mut(expr);
+                            if self.done.not() {
+                                let span = spanned_at(expr.span());
+                                *expr = parse_quote_spanned! {span=>
+                                    return #expr
+                                };
+                                self.done = true;
+                            }
+                        },
+
+                        | _ => {
+                            // Do nothing (do not recurse):
+                            // the return type cannot be `()`
+                            // and yet the block does not end with an expr, so
+                            // unless the last expr diverges there will be a
+                            // type error anyways.
+                        },
+                    }
+                }
+
+                fn visit_expr_mut (
+                    self: &'_ mut Self,
+                    expr: &'_ mut Expr,
+                )
+                {
+                    match *expr {
+                        | Expr::Block(ExprBlock {
+                            ref mut block,
+                            label: None,
+                            ..
+                        })
+                        | Expr::Unsafe(ExprUnsafe {
+                            ref mut block,
+                            ..
+                        }) => {
+                            self.visit_block_mut(block);
+                        },
+
+                        // `'label: { ... break 'label <expr>; ... <expr> }`
+                        | Expr::Block(ExprBlock {
+                            ref mut block,
+                            label: ref mut label @ Some(_),
+                            ..
+                        }) => {
+                            let mut tail_breaks = TailBreaks {
+                                label: label.as_ref().map(|it| &it.name),
+                                loop_depth: None,
+                                label_used: false,
+                            };
+                            tail_breaks.visit_block_mut(block);
+                            if tail_breaks.label_used.not() {
+                                *label = None;
+                            }
+                            self.visit_block_mut(block);
+                        },
+
+                        // `loop { ... break <expr>; ... }`
+                        | Expr::Loop(ExprLoop {
+                            ref mut label,
+                            ref mut body,
+                            ..
+                        }) => {
+                            let mut tail_breaks = TailBreaks {
+                                label: label.as_ref().map(|it| &it.name),
+                                loop_depth: Some(0),
+                                label_used: false,
+                            };
+                            tail_breaks.visit_block_mut(body);
+                            if tail_breaks.label_used.not() {
+                                *label = None;
+                            }
+                            // The loop itself never completes.
+                            self.done = true;
+                        },
+
+                        | Expr::If(ExprIf {
+                            ref mut then_branch,
+                            else_branch: ref mut mb_else_branch,
+                            ..
+                        }) => {
+                            self.visit_block_mut(then_branch);
+                            self.done = false;
+                            if let Some((_, else_)) = mb_else_branch {
+                                self.visit_expr_mut(else_);
+                            } else {
+                                // Do nothing, the return type cannot be `()`
+                                // and yet the block ends with an else-less
+                                // if block, so there will be a type error
+                                // anyways.
+                            }
+                            self.done = true;
+                        },
+
+                        | Expr::Match(ExprMatch {
+                            ref mut arms,
+                            ..
+                        }) => {
+                            for arm in arms {
+                                let body = &mut arm.body;
+                                self.visit_expr_mut(body);
+                                if self.done.not() {
+                                    // handle the non-braced body arm case.
+                                    let span = spanned_at(body.span());
+                                    *body = parse_quote_spanned! {span=>
+                                        return #body
+                                    };
+                                }
+                                self.done = false;
+                            }
+                            self.done = true;
+                        },
+
+                        | _ => {
+                            // Do nothing (do not recurse)
+                        }
+                    }
+                }
+            }
+            /// Replaces the `break <expr>`s yielding the value of a tail
+            /// expression (a `loop` or a labelled block) with
+            /// `return <expr>`s.
+            struct TailBreaks<'__> {
+                label: Option<&'__ Lifetime>,
+                /// The number of nested loops, for a `loop`, since an
+                /// unlabelled `break` refers to the innermost one.
+                loop_depth: Option<usize>,
+                /// Whether a (non-replaced) `break` or `continue` still
+                /// refers to the label, lest it be removed (as an unused one).
+                label_used: bool,
+            }
+            impl VisitMut for TailBreaks<'_> {
+                fn visit_item_mut (
+                    self: &'_ mut Self,
+                    item: &'_ mut Item,
+                )
+                {
+                    // Stop recursing (but for statement macros).
+                    if let Item::Macro(ItemMacro { ident: None, ref mut mac, .. }) = *item {
+                        self.visit_macro_mut(mac);
+                    }
+                }
+
+                fn visit_macro_mut (
+                    self: &'_ mut Self,
+                    mac: &'_ mut Macro,
+                )
+                {
+                    visit_macro_args_mut(self, mac);
+                }
+
+                fn visit_expr_mut (
+                    self: &'_ mut Self,
+                    expr: &'_ mut Expr,
+                )
+                {
+                    match *expr {
+                        | Expr::Async(_)
+                        | Expr::Closure(_)
+                        => {
+                            // Stop visiting
+                        },
+
+                        | Expr::ForLoop(_)
+                        | Expr::Loop(_)
+                        | Expr::While(_)
+                        => {
+                            if let Some(ref mut depth) = self.loop_depth {
+                                *depth += 1;
+                            }
+                            visit_mut::visit_expr_mut(self, expr);
+                            if let Some(ref mut depth) = self.loop_depth {
+                                *depth -= 1;
+                            }
+                        },
+
+                        | Expr::Break(ExprBreak {
+                            ref label,
+                            expr: Some(ref mut value),
+                            break_token,
+                            ..
+                        })
+                            if match *label {
+                                | Some(ref label) => Some(label) == self.label,
+                                | None => self.loop_depth == Some(0),
+                            }
+                        => {
+                            self.visit_expr_mut(value);
+                            let span = spanned_at(break_token.span);
+                            *expr = parse_quote_spanned! {span=>
+                                return #value
+                            };
+                        },
+
+                        | Expr::Break(ExprBreak { label: Some(ref label), .. })
+                        | Expr::Continue(ExprContinue { label: Some(ref label), .. })
+                            if Some(label) == self.label
+                        => {
+                            self.label_used = true;
+                            visit_mut::visit_expr_mut(self, expr);
+                        },
+
+                        | _ => visit_mut::visit_expr_mut(self, expr),
+                    }
+                }
+            }
+            AddExplicitReturns::default().visit_block_mut(block);
+
+            // Then map `return <expr>` to `return cont(<expr>)`.
+            struct ReturnMapper<'__> {
+                continuation_name: &'__ Ident,
+            }
+            impl VisitMut for ReturnMapper<'_> {
+                fn visit_item_mut (
+                    self: &'_ mut Self,
+                    item: &'_ mut Item,
+                )
+                {
+                    // Stop recursing (but for statement macros).
+                    if let Item::Macro(ItemMacro { ident: None, ref mut mac, .. }) = *item {
+                        self.visit_macro_mut(mac);
+                    }
+                }
+
+                fn visit_macro_mut (
+                    self: &'_ mut Self,
+                    mac: &'_ mut Macro,
+                )
+                {
+                    visit_macro_args_mut(self, mac);
+                }
+
+                fn visit_expr_mut (
+                    self: &'_ mut Self,
+                    expr: &'_ mut Expr,
+                )
+                {
+                    let continuation_name = self.continuation_name;
+                    match *expr {
+                        | Expr::Async(_)
+                        | Expr::Closure(_)
+                        => {
+                            // Stop visiting
+                            return;
+                        },
+
+                        // `return <expr>` ...
+                        | Expr::Return(ExprReturn {
+                            expr: Some(ref mut expr),
+                            ..
+                        })
+                            // (but for an explicitly named continuation being
+                            // called by `<expr>` already: `return k(...)`).
+                            if is_call_of(expr, continuation_name).not()
+                        => {
+                            // recurse
+                            self.visit_expr_mut(expr);
+                            // ... becomes `return cont(<expr>)`
+                            let span = spanned_at(expr.span());
+                            *expr = parse_quote_spanned! {span=>
+                                #continuation_name(#expr)
+                            };
+                        },
+
+                        // `<expr>?` carries a hidden `return Err(err.into())`
+                        // inside it, we need to change it:
+                        | Expr::Try(ExprTry {
+                            expr: ref mut inner_expr,
+                            // to span the error-related logic
+                            question_token: token::Question {
+                                spans: [question_span],
+                            },
+                            ..
+                        }) => {
+                            // recurse
+                            self.visit_expr_mut(inner_expr);
+                            proc_macro_use! {
+                                #[spanned(question_span)]
+                                use $krate::{
+                                    Break_, Continue_,
+                                    FromResidual,
+                                    Try,
+                                };
+                            }
+                            let span = spanned_at(question_span);
+                            *expr = parse_quote_spanned! {span=>
+                                match #inner_expr { it => match #Try::branch(it) {
+                                    | #Continue_(it) => it,
+                                    | #Break_(residual) => {
+                                        return #continuation_name(
+                                            #FromResidual::from_residual(residual)
+                                        );
+                                    },
+                                }}
+                            };
+                        },
+
+                        | _ => {
+                            // sub-recurse
+                            visit_mut::visit_expr_mut(self, expr);
+                        },
+                    }
+                }
+            }
+            ReturnMapper {
+                continuation_name: &continuation_name,
+            }.visit_block_mut(block);
+        }
+        proc_macro_use! {
+            use $krate::{Some_};
+        }
+        if recursive {
+            /* We currently have something like:
+            ```rust
+            fn with_foo<R, F : FnOnce(&'_ ()) -> R> (
+                recurse: bool,
+                with: F,
+            ) -> R
+            {
+                if recurse {
+                    with_foo(false, |ret| {
+                        with(ret)
+                    })
+                } else {
+                    with(&())
+                }
+            }
+            ```
+
+            The objetive, to avoid the infinite type recursion, is to replace
+            `<F>` with an `&mut dyn FnMut`, and `<R>` with a return value of
+            `()` (the value will be "returned" through a mutate out slot,
+            thus type-erased by the `dyn FnMut` type erasure).
+
+              - EDIT: Actually, returning `()` is less type-safe, in that a
+                      caller may forget to call the continuation (especially
+                      if they opt out of sugar).
+                      So we only replace `R` with `()` at call site.
+
+            ```rust
+            fn with_foo<R, F : FnOnce(&'_ ()) -> R> (
+                recurse: bool,
+                with: F,
+            ) -> R
+            {
+                let mut ret_slot = None;
+                let mut with = Some(with);
+
+                // wrapped_func…
+                fn __recurse_with_foo<R> (
+                    recurse: bool,
+                    with: &'_ mut (dyn FnMut(&'_ ())) -> R,
+                ) -> R
+                {
+                    if recurse {
+                        with_foo(false, |ret| {
+                            with(ret)
+                        })
+                    } else {
+                        with(&())
+                    }
+                }
+                // …_call
+                __recurse_with_foo(recurse, &mut |ret| -> () {
+                    ret_slot = Some(with.take().unwrap()(ret));
+                });
+
+                ret_slot.unwrap()
+            }
+            ``` */
+            proc_macro_use! {
+                use $krate::{FnMut, None_};
+            }
+            // handle_let_bindings::f(block, with_attrs)?;
+            let wrapped_func_call = ::func_wrap::func_wrap(
+                sig,
+                block.clone(),
+                outer_scope,
+            );
+            if let Some(mut wrapped_func_call) = wrapped_func_call {
+                if let Err(err) =
+                    handle_let_bindings::f(&mut wrapped_func_call.block, with_attrs)
+                {
+                    encountered_error.push_error(err);
+                }
+                wrapped_func_call.sig.ident = format_ident!(
+                    "__recurse_{}",
+                    wrapped_func_call.sig.ident,
+                );
+                let _ = wrapped_func_call.sig.generics.params.pop(); // <…, F>
+                match wrapped_func_call.sig.inputs.last_mut() {
+                    | Some(&mut FnArg::Typed(ref mut pat_ty)) => {
+                        *pat_ty.ty = parse_quote!(
+                            &'_ mut (
+                                dyn #FnMut(#ret) -> __Continuation_Return__
+                                    #bounds
+                            )
+                        );
+                    },
+                    | _ => unreachable!(),
+                }
+                *wrapped_func_call.call_site_args.last_mut().unwrap() =
+                    parse_quote!(
+                        &mut |__ret__: #ret| -> (/* Ensure this is not generic */) {
+                            __ret_slot__ = #Some_(#continuation_name(__ret__));
+                        }
+                    )
+                ;
+                *block = parse_quote!({
+                    let mut __ret_slot__ = #None_;
+                    type __Continuation_Return__ = ();
+                    let () = #wrapped_func_call;
+                    __ret_slot__.expect("\
+                        Fatal `with_locals` error: \
+                        failed to call the continuation.\
+                    ")
+                });
+            } else {
+                // Keep the (non-recursive) transformation.
+                encountered_error.push_error(Error::new(Span::call_site(), "\
+                    Missing `#[with]` on the enscoping `impl` or `trait` block\
+                "));
+            }
+        } // end of recursive-related tranformations.
+        let mut block_prefix = if dyn_safe.is_dyn_safe() { quote!() } else { quote!(
+            /// Some user-provided code patterns, once transformed, may scare
+            /// Rust into thinking we are calling an `FnOnce()` multiple times.
+            /// Since that _shouldn't_ be the case, we defer to a runtime check,
+            /// hoping that, in practice, it will end up being optimized away.
+            extern {}
+            let mut #continuation_name = {
+                let mut #continuation_name =
+                    #Some_(#continuation_name)
+                ;
+                move |__ret__: #ret| {
+                    #continuation_name
+                        .take()
+                        .expect("\
+                            Fatal `with_locals` error: \
+                            attempted to call an `FnOnce()` multiple times.\
+                        ")
+                        (__ret__)
+                }
+            };
+        )};
+        if continuation.is_some() {
+            // Requires Rust 1.40.0
+            block_prefix.extend(quote! {
+                #[allow(unused)]
+                macro_rules! #continuation_name { ($expr:expr) => (
+                    return #continuation_name($expr)
+                )}
+            });
+        }
+        // (Spliced rather than nested, lest `unused_braces` fire on the
+        // user's block.)
+        let stmts = &block.stmts;
+        *block = parse_quote!({
+            #block_prefix
+            #(#stmts)*
+        });
+    }
+    encountered_error.map_or(Ok(()), Err)
+}
@@ -0,0 +1,32 @@
+/// `proc_macro_use! { use $krate::{Foo, Bar}; }` defines `Foo` and `Bar`
+/// variables, holding the `::with_locals::__::{Foo, Bar}` paths (or rather,
+/// `<crate_path()>::__::{Foo, Bar}`).
+///
+/// A `#[spanned(span)]` annotation makes these paths located at `span`, for the
+/// generated code using them to point at the user code it stems from.
+macro_rules! proc_macro_use {(
+    use $dol:tt $krate:ident::{$($item:ident),* $(,)? };
+) => (
+    proc_macro_use! {
+        #[spanned(::proc_macro2::Span::call_site())]
+        use $dol $krate::{$($item),*};
+    }
+);
+(
+    #[spanned($span:expr)]
+    use $dol:tt $krate:ident::{$($item:ident),* $(,)? };
+) => (
+    let span = $crate::helpers::spanned_at($span);
+    let crate_path = $crate::helpers::crate_path(span);
+    let $krate = quote_spanned! {span=>
+        #crate_path::__
+    };
+    let _ = (&$krate, );
+    $(
+        #[allow(nonstandard_style)]
+        let $item = quote_spanned! {span=>
+            #crate_path::__::$item
+        };
+        let _ = (&$krate, );
+    )*
+)}
@@ -0,0 +1,357 @@
+use super::*;
+
+#[macro_use]
+mod macros;
+
+/// Errors are accumulated (rather than bailing out on the first one), so that
+/// a single build reports all the misuses of `#[with]` at once.
+pub(in crate)
+trait PushError {
+    fn push_error (self: &'_ mut Self, err: Error)
+    ;
+}
+
+impl PushError for Option<Error> {
+    fn push_error (self: &'_ mut Option<Error>, err: Error)
+    {
+        match *self {
+            | Some(ref mut errors) => errors.combine(err),
+            | None => *self = Some(err),
+        }
+    }
+}
+
+::std::thread_local! {
+    static CRATE_PATH: ::core::cell::RefCell<Option<Path>> = const {
+        ::core::cell::RefCell::new(None)
+    }
+    ;
+}
+
+/// The path to the `with_locals` crate the generated code refers to, located
+/// at `span`: `::with_locals`, unless overridden with a
+/// `crate = path::to::with_locals` argument (_e.g._, for a facade crate
+/// re-exporting `#[with]`).
+pub(in crate)
+fn crate_path (span: Span)
+  -> TokenStream2
+{
+    CRATE_PATH.with(|it| match *it.borrow() {
+        | Some(ref path) => {
+            path.to_token_stream()
+                .into_iter()
+                .map(|mut tt| { tt.set_span(span); tt })
+                .collect()
+        },
+        | None => quote_spanned!(span=> ::with_locals ),
+    })
+}
+
+/// Runs `f` with `path` (if any) as the [`crate_path()`].
+pub(in crate)
+fn with_crate_path<R> (path: Option<&'_ Path>, f: impl FnOnce() -> R)
+  -> R
+{
+    let path = match path {
+        | Some(path) => path.clone(),
+        | None => return f(),
+    };
+    let prev = CRATE_PATH.with(|it| it.replace(Some(path)));
+    let ret = f();
+    CRATE_PATH.with(|it| *it.borrow_mut() = prev);
+    ret
+}
+
+/// The standard library macros whose arguments are comma-separated
+/// expressions (a leading format string literal being one too), within which
+/// `return`s and `?`s are to be rewritten as in the rest of the code.
+const EXPR_MACROS: &[&str] = &[
+    "assert", "assert_eq", "assert_ne",
+    "dbg",
+    "debug_assert", "debug_assert_eq", "debug_assert_ne",
+    "eprint", "eprintln",
+    "format", "format_args",
+    "panic",
+    "print", "println",
+    "todo", "unimplemented", "unreachable",
+    "vec",
+    "write", "writeln",
+];
+
+::std::thread_local! {
+    static EXTRA_MACROS: ::core::cell::RefCell<Vec<Ident>> = const {
+        ::core::cell::RefCell::new(Vec::new())
+    }
+    ;
+}
+
+/// Runs `f` with the `macros = [...]` ones being handled like the
+/// [`EXPR_MACROS`].
+pub(in crate)
+fn with_extra_macros<R> (macros: &'_ [Ident], f: impl FnOnce() -> R)
+  -> R
+{
+    let len = EXTRA_MACROS.with(|it| {
+        let mut it = it.borrow_mut();
+        it.extend(macros.iter().cloned());
+        it.len() - macros.len()
+    });
+    let ret = f();
+    EXTRA_MACROS.with(|it| it.borrow_mut().truncate(len));
+    ret
+}
+
+/// Makes `visitor` descend into the arguments of `mac`, when these are known to
+/// be expressions (see [`EXPR_MACROS`]); other macro invocations are opaque.
+pub(in crate)
+fn visit_macro_args_mut (
+    visitor: &'_ mut (impl VisitMut + ?Sized),
+    mac: &'_ mut Macro,
+)
+{
+    let name = match mac.path.segments.last() {
+        | Some(it) => it.ident.to_string(),
+        | None => return,
+    };
+    let is_expr_macro =
+        EXPR_MACROS.contains(&&*name)
+        || EXTRA_MACROS.with(|it| it.borrow().iter().any(|it| *it == name))
+    ;
+    if name == "matches" {
+        // `matches!(<expr>, <pattern>)`
+        let parse_matches = |input: ParseStream<'_>| Ok((
+            input.parse::<Expr>()?,
+            input.parse::<TokenStream2>()?,
+        ));
+        if let Ok((mut expr, rest)) = mac.parse_body_with(parse_matches) {
+            visitor.visit_expr_mut(&mut expr);
+            mac.tokens = quote!( #expr #rest );
+        }
+        return;
+    }
+    if name == "vec" {
+        // `vec![<expr>; <len>]`
+        if let Ok((mut elem, semi, mut len)) = mac.parse_body_with(vec_repeat) {
+            visitor.visit_expr_mut(&mut elem);
+            visitor.visit_expr_mut(&mut len);
+            mac.tokens = quote!( #elem #semi #len );
+            return;
+        }
+    }
+    if is_expr_macro {
+        let parse_args = Punctuated::<Expr, Token![,]>::parse_terminated;
+        if let Ok(mut args) = mac.parse_body_with(parse_args) {
+            args.iter_mut().for_each(|arg| visitor.visit_expr_mut(arg));
+            mac.tokens = args.into_token_stream();
+        }
+    }
+
+    fn vec_repeat (input: ParseStream<'_>)
+      -> Result<(Expr, Token![;], Expr)>
+    {
+        Ok((input.parse()?, input.parse()?, input.parse()?))
+    }
+}
+
+/// Whether `ident` occurs within `tts` (including within nested groups).
+pub(in crate)
+fn mentions_ident (tts: TokenStream2, ident: &'_ str)
+  -> bool
+{
+    tts.into_iter().any(|tt| match tt {
+        | ::proc_macro2::TokenTree::Ident(it) => it == ident,
+        | ::proc_macro2::TokenTree::Group(group) => {
+            mentions_ident(group.stream(), ident)
+        },
+        | _ => false,
+    })
+}
+
+/// Whether `expr` is literally a call of `ident`: `ident(...)`.
+pub(in crate)
+fn is_call_of (expr: &'_ Expr, ident: &'_ Ident)
+  -> bool
+{
+    match *expr {
+        | Expr::Call(ExprCall { ref func, .. }) => match **func {
+            | Expr::Path(ExprPath { qself: None, ref path, .. }) => {
+                path.is_ident(ident)
+            },
+            | _ => false,
+        },
+        | Expr::Paren(ExprParen { expr: ref inner, .. }) => {
+            is_call_of(inner, ident)
+        },
+        | _ => false,
+    }
+}
+
+/// A span resolving names like `Span::call_site()` does, but located at `span`,
+/// so that the generated code points at the user code it stems from (for error
+/// messages, lints, and IDEs).
+pub(in crate)
+fn spanned_at (span: Span)
+  -> Span
+{
+    Span::call_site().located_at(span)
+}
+
+/// `foo` becomes `with_foo`, or whatever the `name` pattern dictates.
+pub(in crate)
+fn with_ident (name: &'_ str, ident: &'_ Ident)
+  -> Ident
+{
+    format_ident!(
+        "{}", name.replace("{}", &ident.unraw().to_string()),
+        span = ident.span(),
+    )
+}
+
+/// The attributes of an expression, if it can have any.
+pub(in crate)
+fn expr_attrs_mut (expr: &'_ mut Expr)
+  -> Option<&'_ mut Vec<Attribute>>
+{
+    macro_rules! with_attrs {( $($Variant:ident),* $(,)? ) => (
+        match *expr {
+            $(
+                | Expr::$Variant(ref mut it) => Some(&mut it.attrs),
+            )*
+            | _ => None,
+        }
+    )}
+    with_attrs!(
+        Array, Assign, AssignOp, Async, Await, Binary, Block, Box, Break, Call,
+        Cast, Closure, Continue, Field, ForLoop, Group, If, Index, Let, Lit,
+        Loop, Macro, Match, MethodCall, Paren, Path, Range, Reference, Repeat,
+        Return, Struct, Try, TryBlock, Tuple, Type, Unary, Unsafe, While, Yield,
+    )
+}
+
+pub(in crate)
+struct LifetimeVisitor<'__> {
+    pub(in crate)
+    lifetime: &'__ str,
+
+    pub(in crate)
+    lifetimes: &'__ mut Vec<(/*Lifetime*/)>,
+}
+
+impl VisitMut for LifetimeVisitor<'_> {
+    fn visit_lifetime_mut (
+        self: &'_ mut Self,
+        lifetime: &'_ mut Lifetime,
+    )
+    {
+        if lifetime.ident == self.lifetime {
+            // lifetime.ident = format_ident!(
+            //     "__self_{}__", self.lifetimes.len(),
+            //     span = lifetime.ident.span(),
+            // );
+            lifetime.ident =
+                format_ident!("_", span = lifetime.ident.span())
+            ;
+            self.lifetimes.push({ /* lifetime.clone() */ });
+        }
+    }
+}
+
+pub(in crate)
+struct Fields<'fun> {
+    // pub attrs: &'fun mut Vec<Attribute>,
+    // pub vis: Option<&'fun mut Visibility>,
+    pub sig: &'fun mut Signature,
+    pub block: Option<&'fun mut Block>,
+}
+
+pub(in crate)
+trait FnLike {
+    fn fields<'fun> (self: &'fun mut Self)
+      -> Fields<'fun>
+    ;
+}
+
+impl FnLike for ItemFn {
+    fn fields<'fun> (self: &'fun mut ItemFn)
+      -> Fields<'fun>
+    {
+        let ItemFn {
+            // ref mut attrs,
+            // ref mut vis,
+            ref mut sig,
+            ref mut block,
+            ..
+        } = *self;
+        Fields {
+            // attrs,
+            // vis: Some(vis),
+            sig,
+            block: Some(block),
+        }
+    }
+}
+
+impl FnLike for ImplItemMethod {
+    fn fields<'fun> (self: &'fun mut ImplItemMethod)
+      -> Fields<'fun>
+    {
+        let ImplItemMethod {
+            // ref mut attrs,
+            // ref mut vis,
+            ref mut sig,
+            ref mut block,
+            ..
+        } = *self;
+        Fields {
+            // attrs,
+            // vis: Some(vis),
+            sig,
+            block: Some(block),
+        }
+    }
+}
+
+impl FnLike for TraitItemMethod {
+    fn fields<'fun> (self: &'fun mut TraitItemMethod)
+      -> Fields<'fun>
+    {
+        let TraitItemMethod {
+            // ref mut attrs,
+            ref mut sig,
+            default: ref mut block,
+            ..
+        } = *self;
+        Fields {
+            // attrs,
+            // vis: None,
+            sig,
+            block: block.as_mut(),
+        }
+    }
+}
+
+/// Formats `code` with `rustfmt`, if available.
+pub
+fn rustfmt (code: &'_ str)
+  -> Option<String>
+{Some({
+    let mut child =
+        ::std::process::Command::new("rustfmt")
+            .args(["--edition", "2018"])
+            .stdin(::std::process::Stdio::piped())
+            .stdout(::std::process::Stdio::piped())
+            .stderr(::std::process::Stdio::piped())
+            .spawn()
+            .ok()?
+    ;
+    match child.stdin.take().unwrap() { ref mut stdin => {
+        ::std::io::Write::write_all(stdin, code.as_bytes()).ok()?;
+    }}
+    let mut stdout = String::new();
+    ::std::io::Read::read_to_string(
+        &mut child.stdout.take().unwrap(),
+        &mut stdout,
+    ).ok()?;
+    if child.wait().ok()?.success().not() { return None; }
+    stdout
+})}
@@ -0,0 +1,812 @@
+//! The transformations behind the [`#[with]`](https://docs.rs/with_locals)
+//! attribute, as a normal library operating on [`proc_macro2`] types.
+//!
+//! This lets other procedural macros (or build scripts) apply the CPS
+//! desugaring of `with_locals` to the code they generate.
+//!
+//! The generated code refers to the helpers of the `with_locals` crate, which
+//! must thus be reachable from the crate where the expansion ends up: as a
+//! dependency, or through a re-export named by a `crate = path` argument.
+
+#![allow(nonstandard_style)]
+// The `self: &'_ Self` and `let ref x = ...` spellings are deliberate.
+#![allow(clippy::needless_arbitrary_self_type, clippy::toplevel_ref_arg)]
+
+extern crate proc_macro;
+
+use ::proc_macro2::{
+    Span,
+    TokenStream as TokenStream2,
+};
+use ::quote::{
+    format_ident,
+    quote,
+    quote_spanned,
+    ToTokens,
+};
+use ::syn::{*,
+    ext::IdentExt,
+    parse::{
+        // Nothing,
+        Parse,
+        // Parser,
+        ParseStream,
+    },
+    punctuated::Punctuated,
+    spanned::Spanned,
+    Result,
+    visit_mut::{self, VisitMut},
+};
+
+use ::core::{
+    mem,
+    ops::Not as _,
+};
+
+use self::{
+    helpers::{
+        expr_attrs_mut, is_call_of, mentions_ident, spanned_at,
+        visit_macro_args_mut, with_crate_path, with_extra_macros, with_ident,
+        Fields as __, FnLike, LifetimeVisitor, PushError,
+    },
+};
+
+#[macro_use]
+mod helpers;
+
+mod attrs;
+mod config;
+#[cfg(feature = "expand-macros")]
+mod expand_macros;
+include!("handle_returning_locals.rs");
+mod handle_let_bindings;
+mod wrap_statements_inside_closure_body;
+
+type Str = ::std::borrow::Cow<'static, str>;
+
+pub use attrs::Attrs;
+pub use config::with_manifest_path;
+pub use helpers::rustfmt;
+use attrs::{CallSiteAttrs, DynSafe};
+
+/// The whole `#[with(attrs)] input` expansion, as performed by the attribute.
+///
+/// `input` may be an `fn`, an `impl` block, a `trait` definition, or an inline
+/// `mod`.
+pub
+fn expand (
+    attrs: TokenStream2,
+    input: TokenStream2,
+) -> Result<TokenStream2>
+{
+    let mut encountered_error = None;
+    let ret = expand_best_effort(attrs, input, &mut encountered_error);
+    encountered_error.map_or(Ok(ret), Err)
+}
+
+/// Like [`expand`], but for the errors being emitted (as `compile_error!`s)
+/// alongside a best-effort expansion.
+///
+/// That way, a mistake within the body of a with-function does not make its
+/// `with_`-prefixed signature vanish, which would otherwise bury the genuine
+/// error under a bunch of "cannot find function" ones at every call site.
+///
+/// This is what the `#[with]` attribute uses.
+pub
+fn expand_recovering (
+    attrs: TokenStream2,
+    input: TokenStream2,
+) -> TokenStream2
+{
+    let mut encountered_error = None;
+    let mut ret = expand_best_effort(attrs, input, &mut encountered_error);
+    if let Some(err) = encountered_error {
+        ret.extend(err.to_compile_error());
+    }
+    ret
+}
+
+/// The `with_block! { ... }` expansion: the `let` sugar of `#[with]` applied to
+/// the statements of a block expression, without needing to annotate the
+/// enclosing function.
+///
+/// The `#[with]` arguments, if any, are given as a leading inner attribute:
+/// `with_block! { #![with('local)] ... }`. A `return` or a `?` within the
+/// block returns from the enclosing function or closure, as usual.
+pub
+fn expand_block (input: TokenStream2)
+  -> Result<TokenStream2>
+{
+    let mut encountered_error = None;
+    let ret = expand_block_best_effort(input, &mut encountered_error);
+    encountered_error.map_or(Ok(ret), Err)
+}
+
+/// Like [`expand_block`], but for the errors being emitted (as
+/// `compile_error!`s) alongside a best-effort expansion.
+///
+/// This is what the `with_block!` macro uses.
+pub
+fn expand_block_recovering (input: TokenStream2)
+  -> TokenStream2
+{
+    let mut encountered_error = None;
+    let ret = expand_block_best_effort(input, &mut encountered_error);
+    match encountered_error {
+        | Some(err) => {
+            let err = err.to_compile_error();
+            quote!({ #err #ret })
+        },
+        | None => ret,
+    }
+}
+
+fn expand_block_best_effort (
+    input: TokenStream2,
+    encountered_error: &'_ mut Option<Error>,
+) -> TokenStream2
+{
+    let parse_block = |input: ParseStream<'_>| Ok((
+        Attribute::parse_inner(input)?,
+        Block::parse_within(input)?,
+    ));
+    let (mut inner_attrs, stmts) =
+        match ::syn::parse::Parser::parse2(parse_block, input) {
+            | Ok(it) => it,
+            | Err(err) => {
+                encountered_error.push_error(err);
+                return quote!({});
+            },
+        }
+    ;
+    let mut block = Block {
+        brace_token: Default::default(),
+        stmts,
+    };
+    let attrs = match take_with_attr(&mut inner_attrs) {
+        | Ok(Some(attr)) if attr.tokens.is_empty().not() => {
+            attr.parse_args().unwrap_or_else(|err| {
+                encountered_error.push_error(err);
+                TokenStream2::new()
+            })
+        },
+        | Ok(_) => TokenStream2::new(),
+        | Err(err) => {
+            encountered_error.push_error(err);
+            TokenStream2::new()
+        },
+    };
+    let ref attrs = Attrs::parse_recovering(attrs, encountered_error);
+    with_crate_path(attrs.krate.as_ref(), || with_extra_macros(&attrs.macros, || {
+        if let Err(err) = handle_let_bindings::f(&mut block, attrs) {
+            encountered_error.push_error(err);
+        }
+    }));
+    block.stmts.splice(0 .. 0, config::manifest_dependency());
+    let stmts = &block.stmts;
+    quote!({
+        #(#inner_attrs)*
+        #(#stmts)*
+    })
+}
+
+fn expand_best_effort (
+    attrs: TokenStream2,
+    input: TokenStream2,
+    encountered_error: &'_ mut Option<Error>,
+) -> TokenStream2
+{
+    if let Some(ref err) = config::config().error {
+        encountered_error.push_error(Error::new(Span::call_site(), err));
+    }
+    let ref attrs = Attrs::parse_recovering(attrs, encountered_error);
+    #[cfg(feature = "expand-macros")]
+    let (mut name, orig_input) = (String::new(), input.clone());
+    let ret = match parse2::<TraitItemMethod>(input.clone()) {
+        | Ok(mut method) => {
+            #[cfg(feature = "expand-macros")] {
+                name = method.sig.ident.to_string();
+            }
+            match method.default {
+                | Some(block) => {
+                    let TraitItemMethod { attrs: fn_attrs, sig, .. } = method;
+                    with_any_fn(attrs, ItemFn {
+                        attrs: fn_attrs,
+                        vis: Visibility::Inherited,
+                        sig,
+                        block: Box::new(block),
+                    }, encountered_error)
+                },
+                | None => {
+                    if let Err(err) = handle_fn_like(attrs, &mut method, None) {
+                        encountered_error.push_error(err);
+                    }
+                    method.into_token_stream()
+                },
+            }
+        },
+        | Err(_) => match parse2(input.clone()) {
+            | Ok(Item::Impl(item)) => {
+                #[cfg(feature = "expand-macros")] {
+                    name = match item.trait_ {
+                        | Some((_, ref trait_, _)) => format!(
+                            "impl {} for {}",
+                            trait_.to_token_stream(),
+                            item.self_ty.to_token_stream(),
+                        ),
+                        | None => format!("impl {}", item.self_ty.to_token_stream()),
+                    };
+                }
+                with_impl(attrs, item, encountered_error)
+            },
+            | Ok(Item::Trait(item)) => {
+                #[cfg(feature = "expand-macros")] {
+                    name = item.ident.to_string();
+                }
+                with_trait(attrs, item, encountered_error)
+            },
+            | Ok(Item::Fn(fun)) => {
+                #[cfg(feature = "expand-macros")] {
+                    name = fun.sig.ident.to_string();
+                }
+                with_visible_fn(attrs, fun, encountered_error)
+            },
+            | Ok(Item::Mod(item)) => {
+                #[cfg(feature = "expand-macros")] {
+                    name = item.ident.to_string();
+                }
+                with_mod(attrs, item, encountered_error)
+            },
+            | _otherwise => {
+                encountered_error.push_error(Error::new(Span::call_site(), "\
+                    `#[with]` can only be applied to \
+                    an `fn`, a `trait`, an `impl`, or an inline `mod`.\
+                "));
+                input
+            },
+        },
+    };
+    #[cfg(feature = "expand-macros")] {
+        expand_macros::persist_expansion(
+            &orig_input,
+            &ret,
+            &name,
+        );
+    }
+    ret
+}
+
+/// Expands a `#[with(attrs)]`-annotated `fn`.
+///
+/// A function returning a reference to a local is turned, in place, into a
+/// `with_`-prefixed function taking a continuation.
+pub
+fn expand_fn (attrs: &'_ Attrs, fun: ItemFn)
+  -> Result<TokenStream2>
+{
+    let mut encountered_error = None;
+    let ret = with_any_fn(attrs, fun, &mut encountered_error);
+    encountered_error.map_or(Ok(ret), Err)
+}
+
+/// Expands a `#[with(attrs)]`-annotated `impl` block: `attrs` apply to every
+/// method, but for the arguments its own `#[with(...)]` attribute overrides.
+pub
+fn expand_impl (attrs: &'_ Attrs, impl_: ItemImpl)
+  -> Result<TokenStream2>
+{
+    let mut encountered_error = None;
+    let ret = with_impl(attrs, impl_, &mut encountered_error);
+    encountered_error.map_or(Ok(ret), Err)
+}
+
+/// Expands a `#[with(attrs)]`-annotated `trait` definition: `attrs` apply to
+/// every method, but for the arguments its own `#[with(...)]` attribute
+/// overrides.
+pub
+fn expand_trait (attrs: &'_ Attrs, trait_: ItemTrait)
+  -> Result<TokenStream2>
+{
+    let mut encountered_error = None;
+    let ret = with_trait(attrs, trait_, &mut encountered_error);
+    encountered_error.map_or(Ok(ret), Err)
+}
+
+/// Expands a `#[with(attrs)]`-annotated inline `mod`: `attrs` apply to every
+/// item, but for the arguments its own `#[with(...)]` attribute overrides.
+pub
+fn expand_mod (attrs: &'_ Attrs, mod_: ItemMod)
+  -> Result<TokenStream2>
+{
+    let mut encountered_error = None;
+    let ret = with_mod(attrs, mod_, &mut encountered_error);
+    encountered_error.map_or(Ok(ret), Err)
+}
+
+/// Handles an `fn` with a body, be it free or a method, in place.
+///
+/// (Such a standalone `fn` cannot be told apart from an associated function
+/// of a non-annotated `trait` block or trait `impl`, so it gets no shadow item:
+/// see [`with_visible_fn`].)
+fn with_any_fn (
+    attrs: &'_ Attrs,
+    mut fun: ItemFn,
+    encountered_error: &'_ mut Option<Error>,
+) -> TokenStream2
+{
+    if let Err(err) = handle_fn_like(attrs, &mut fun, None) {
+        encountered_error.push_error(err);
+    }
+    fun.into_token_stream()
+}
+
+/// Handles an `fn` with an explicit visibility (be it `pub(self)`), which can
+/// thus only be a free function or an associated function of an inherent
+/// `impl`, the items of a `trait` and of its `impl`s having none.
+///
+/// Either way, a sibling item is allowed, so that, when turned into a
+/// `with_`-function, it is accompanied by a hidden "shadow" `fn` named after
+/// the original function (see [`with_fn`]).
+fn with_visible_fn (
+    attrs: &'_ Attrs,
+    mut fun: ItemFn,
+    encountered_error: &'_ mut Option<Error>,
+) -> TokenStream2
+{
+    with_crate_path(attrs.krate.as_ref(), || {
+        let ident = fun.sig.ident.clone();
+        if let Err(err) = handle_fn_like(attrs, &mut fun, None) {
+            encountered_error.push_error(err);
+        }
+        if fun.sig.ident == ident || matches!(fun.vis, Visibility::Inherited) {
+            return fun.into_token_stream();
+        }
+        let cfgs = cfg_attrs(&fun.attrs);
+        let shadow_fn = shadow_fn(&fun.vis, &ident, &fun.sig.ident);
+        quote! {
+            #fun
+
+            #(#cfgs)*
+            #[allow(dead_code, nonstandard_style)]
+            #shadow_fn
+        }
+    })
+}
+
+/// The hidden, deprecated `fn` named after the original `ident` of the
+/// `with_ident` function, whose note explains how to call the latter.
+fn shadow_fn (vis: &'_ Visibility, ident: &'_ Ident, with_ident: &'_ Ident)
+  -> TokenStream2
+{
+    let note = format!(
+        "\n\
+        `{ident}` is a with-function: \
+        the value it lends can only be used within a continuation.\n  \
+          - either use `#[with] let x = {ident}(...);` \
+            (inside a `#[with]`-annotated function),\n  \
+          - or call `{with_ident}(..., |x| {{ ... }})` directly.\
+        ",
+        ident = ident,
+        with_ident = with_ident,
+    );
+    proc_macro_use! {
+        use $krate::{WithFunction};
+    }
+    // A `fn` rather than a `const`, since the latter would turn the
+    // bindings (_e.g._, closure parameters) named `#ident` into patterns.
+    quote_spanned!(ident.span()=>
+        #[doc(hidden)]
+        #[deprecated(note = #note)]
+        #vis fn #ident<T> (_: #WithFunction) -> T { ::core::panic!() }
+    )
+}
+
+/// On error, `fun` is still transformed on a best-effort basis.
+fn handle_fn_like<Fun : FnLike> (
+    attrs: &'_ Attrs,
+    fun: &'_ mut Fun,
+    outer_scope: Option<(&'_ Generics, ::func_wrap::ImplOrTrait<'_>)>
+) -> Result<()>
+{
+    with_crate_path(attrs.krate.as_ref(), || with_extra_macros(&attrs.macros, || {
+        let mut encountered_error = None;
+        if let Err(err) = handle_returning_locals(fun, attrs, outer_scope) {
+            encountered_error.push_error(err);
+        }
+        if let Some(block) = fun.fields().block {
+            if let Err(err) = handle_let_bindings::f(block, attrs) {
+                encountered_error.push_error(err);
+            }
+            block.stmts.splice(0 .. 0, config::manifest_dependency());
+        }
+        encountered_error.map_or(Ok(()), Err)
+    }))
+}
+
+fn with_impl (
+    outer_with_attrs: &'_ Attrs,
+    mut impl_: ItemImpl,
+    encountered_error: &'_ mut Option<Error>,
+) -> TokenStream2
+{
+    let ref outer_with_attrs = outer_with_attrs.reject_rename(encountered_error);
+    let outer_scope = (
+        &impl_.generics,
+        ::func_wrap::ImplOrTrait::ImplMethod {
+            implementor: &impl_.self_ty,
+            trait_name: impl_.trait_.as_ref().map(|(_, it, _)| it)
+        },
+    );
+    impl_.items.iter_mut().for_each(|it| match it {
+        | &mut ImplItem::Method(ref mut method) => {
+            let ref mut storage = None;
+            let attrs = inner_attrs(
+                outer_with_attrs,
+                &mut method.attrs,
+                storage,
+                encountered_error,
+            );
+            if let Some(attrs) = attrs {
+                if let Err(err) = handle_fn_like(attrs, method, Some(outer_scope)) {
+                    encountered_error.push_error(err);
+                }
+            }
+        },
+        | _ => {},
+    });
+    impl_.into_token_stream()
+}
+
+
+fn with_trait (
+    outer_with_attrs: &'_ Attrs,
+    mut trait_: ItemTrait,
+    encountered_error: &'_ mut Option<Error>,
+) -> TokenStream2
+{
+    let ref outer_with_attrs = outer_with_attrs.reject_rename(encountered_error);
+    let outer_scope = (
+        &trait_.generics,
+        ::func_wrap::ImplOrTrait::DefaultMethod { trait_name: &trait_.ident },
+    );
+    trait_.items.iter_mut().for_each(|it| match it {
+        | &mut TraitItem::Method(ref mut method) => {
+            let ref mut storage = None;
+            let attrs = inner_attrs(
+                outer_with_attrs,
+                &mut method.attrs,
+                storage,
+                encountered_error,
+            );
+            if let Some(attrs) = attrs {
+                if let Err(err) = handle_fn_like(attrs, method, Some(outer_scope)) {
+                    encountered_error.push_error(err);
+                }
+            }
+        },
+        | _ => {},
+    });
+    trait_.into_token_stream()
+}
+
+/// Applies the `#[with]` attributes to every `fn`, `impl` block, `trait`
+/// definition, and (recursively) inline `mod` inside an inline `mod`, so that
+/// the shared arguments need not be repeated on each of them.
+///
+/// This is also the way to define a group of mutually recursive
+/// with-functions: every member of the group which calls itself back (through
+/// the others) is made `recursive = true`, thus getting its own `dyn`-erased
+/// trampoline, so that the calls between them do not lead to an infinitely
+/// growing chain of monomorphized continuation types.
+fn with_mod (
+    outer_with_attrs: &'_ Attrs,
+    mut mod_: ItemMod,
+    encountered_error: &'_ mut Option<Error>,
+) -> TokenStream2
+{
+    let ref outer_with_attrs = outer_with_attrs.reject_rename(encountered_error);
+    let items = match mod_.content {
+        | Some((_, ref mut items)) => items,
+        | None => {
+            encountered_error.push_error(Error::new_spanned(mod_.semi, "\
+                `#[with]` can only be applied to an inline `mod { ... }`\
+            "));
+            return mod_.into_token_stream();
+        },
+    };
+    let items_and_attrs: Vec<(Item, Option<Attrs>)> =
+        mem::take(items).into_iter().map(|mut it| {
+            let ref mut storage = None;
+            let attrs = match it {
+                | Item::Fn(ItemFn { ref mut attrs, .. })
+                | Item::Impl(ItemImpl { ref mut attrs, .. })
+                | Item::Trait(ItemTrait { ref mut attrs, .. })
+                | Item::Mod(ItemMod { ref mut attrs, content: Some(_), .. })
+                => {
+                    inner_attrs(outer_with_attrs, attrs, storage, encountered_error)
+                        .cloned()
+                },
+                | _ => None,
+            };
+            (it, attrs)
+        })
+        .collect()
+    ;
+    let items_and_attrs = imply_recursive(items_and_attrs);
+    *items = items_and_attrs.into_iter().fold(vec![], |mut acc, (it, attrs)| {
+        match (attrs.as_ref(), it) {
+            | (Some(attrs), Item::Fn(fun)) => {
+                acc.extend(with_fn(attrs, fun, encountered_error));
+            },
+            | (Some(attrs), Item::Impl(impl_)) => {
+                acc.push(Item::Verbatim(
+                    with_impl(attrs, impl_, encountered_error)
+                ));
+            },
+            | (Some(attrs), Item::Trait(trait_)) => {
+                acc.push(Item::Verbatim(
+                    with_trait(attrs, trait_, encountered_error)
+                ));
+            },
+            | (Some(attrs), Item::Mod(mod_)) => {
+                acc.push(Item::Verbatim(
+                    with_mod(attrs, mod_, encountered_error)
+                ));
+            },
+            | (_, it) => acc.push(it),
+        }
+        acc
+    });
+    mod_.into_token_stream()
+}
+
+/// The `fn`s of a `#[with] mod` which (directly or not) call themselves back
+/// get `recursive = true` implied, since, otherwise, the chain of continuation
+/// types would grow infinitely when monomorphizing them.
+///
+/// A `fn` is deemed to call another one of the group whenever its body
+/// mentions the latter's name (or that of its with-function).
+///
+/// (This is not done for continuations with a lifetime bound, those being
+/// incompatible with `recursive = true`.)
+fn imply_recursive (mut items_and_attrs: Vec<(Item, Option<Attrs>)>)
+  -> Vec<(Item, Option<Attrs>)>
+{
+    let with_fns: Vec<(usize, &ItemFn, &Attrs)> =
+        items_and_attrs
+            .iter()
+            .enumerate()
+            .filter_map(|(i, it)| match *it {
+                | (Item::Fn(ref fun), Some(ref attrs)) => {
+                    let mut ret_ty = match fun.sig.output {
+                        | ReturnType::Type(_, ref ty) => (**ty).clone(),
+                        | ReturnType::Default => return None,
+                    };
+                    let mut lifetimes = vec![];
+                    LifetimeVisitor { lifetimes: &mut lifetimes, lifetime: &attrs.lifetime }
+                        .visit_type_mut(&mut ret_ty)
+                    ;
+                    if lifetimes.is_empty() { None } else { Some((i, fun, attrs)) }
+                },
+                | _ => None,
+            })
+            .collect()
+    ;
+    let calls: Vec<Vec<usize>> =
+        with_fns
+            .iter()
+            .map(|&(_, caller, _)| {
+                let body = caller.block.to_token_stream();
+                with_fns
+                    .iter()
+                    .enumerate()
+                    .filter(|&(_, &(_, callee, attrs))| {
+                        let ident = &callee.sig.ident;
+                        mentions_ident(body.clone(), &ident.to_string())
+                        || mentions_ident(
+                            body.clone(),
+                            &with_ident(&attrs.name, ident).to_string(),
+                        )
+                    })
+                    .map(|(j, _)| j)
+                    .collect()
+            })
+            .collect()
+    ;
+    // Whether `start` can reach itself back through the calls.
+    let calls_itself_back = |start: usize| {
+        let mut seen = vec![false; calls.len()];
+        let mut to_visit = calls[start].clone();
+        while let Some(cur) = to_visit.pop() {
+            if cur == start {
+                return true;
+            }
+            if mem::replace(&mut seen[cur], true).not() {
+                to_visit.extend(&calls[cur]);
+            }
+        }
+        false
+    };
+    let recursive_ones: Vec<usize> =
+        (0 .. with_fns.len())
+            .filter(|&j| calls_itself_back(j))
+            .map(|j| with_fns[j].0)
+            .collect()
+    ;
+    for i in recursive_ones {
+        if let Some(ref mut attrs) = items_and_attrs[i].1 {
+            let has_lifetime_bound =
+                attrs
+                    .continuation_bounds
+                    .iter()
+                    .any(|bound| matches!(bound, TypeParamBound::Lifetime(_)))
+            ;
+            if has_lifetime_bound.not() {
+                attrs.recursive = true;
+            }
+        }
+    }
+    items_and_attrs
+}
+
+/// Handles a free function of a `#[with] mod`, which, when turned into a
+/// `with_`-function, is accompanied by a hidden "shadow" `fn` named after the
+/// original function.
+///
+/// That way, forgetting the `#[with]` in `let s = hex(1);` no longer results
+/// in a confusing "cannot find function `hex`" error, but in a type error
+/// (its only parameter being a `WithFunction`) accompanied by a deprecation
+/// note explaining how to call `hex`.
+///
+/// The shadow item is brought into scope through a glob import, so that any
+/// real item with the same name takes precedence over it. These extra items
+/// are only valid among the items of a module, hence the need for the
+/// `#[with] mod` (a standalone function gets a plain sibling one instead, see
+/// [`with_visible_fn`]), and they are subject to the same `#[cfg]`s as the
+/// function.
+fn with_fn (
+    attrs: &'_ Attrs,
+    mut fun: ItemFn,
+    encountered_error: &'_ mut Option<Error>,
+) -> Vec<Item>
+{
+    with_crate_path(attrs.krate.as_ref(), || {
+        let ident = fun.sig.ident.clone();
+        if let Err(err) = handle_fn_like(attrs, &mut fun, None) {
+            encountered_error.push_error(err);
+        }
+        if fun.sig.ident == ident {
+            // Not a with-function: nothing to shadow.
+            return vec![fun.into()];
+        }
+        let with_ident = &fun.sig.ident;
+        let cfgs = cfg_attrs(&fun.attrs);
+        let shadow_mod = format_ident!("__with_locals_{}", ident);
+        let vis = &fun.vis;
+        // Call sites use the crate-wide name, so a custom-named function is also
+        // made available under it.
+        let default_with_ident = helpers::with_ident(&config::config().name, &ident);
+        let alias = if *with_ident != default_with_ident {
+            Some::<Item>(parse_quote! {
+                #(#cfgs)*
+                #[doc(hidden)]
+                #[allow(unused_imports)]
+                #vis use #with_ident as #default_with_ident;
+            })
+        } else {
+            None
+        };
+        let shadow_fn = shadow_fn(&parse_quote!(pub), &ident, with_ident);
+        vec![
+            fun.clone().into(),
+            parse_quote! {
+                #(#cfgs)*
+                #[doc(hidden)]
+                #[allow(dead_code, nonstandard_style)]
+                mod #shadow_mod {
+                    #shadow_fn
+                }
+            },
+            parse_quote! {
+                #(#cfgs)*
+                #[doc(hidden)]
+                #[allow(unused_imports)]
+                #vis use #shadow_mod::*;
+            },
+        ].into_iter().chain(alias).collect()
+    })
+}
+
+/// The `#[cfg(...)]` attributes among `attrs`, as well as the `#[cfg(...)]`
+/// ones a `#[cfg_attr(<predicate>, ...)]` may expand to.
+fn cfg_attrs (attrs: &'_ [Attribute])
+  -> Vec<Attribute>
+{
+    attrs.iter().filter_map(|attr| {
+        if attr.path.is_ident("cfg") {
+            return Some(attr.clone());
+        }
+        if attr.path.is_ident("cfg_attr").not() {
+            return None;
+        }
+        let mut args = match attr.parse_meta() {
+            | Ok(Meta::List(MetaList { nested, .. })) => nested.into_iter(),
+            | _ => return None,
+        };
+        let predicate = args.next()?;
+        let cfgs: Vec<NestedMeta> =
+            args.filter(|arg| matches!(
+                *arg,
+                NestedMeta::Meta(Meta::List(ref it)) if it.path.is_ident("cfg")
+            ))
+            .collect()
+        ;
+        if cfgs.is_empty() {
+            return None;
+        }
+        Some(parse_quote!( #[cfg_attr(#predicate, #(#cfgs),*)] ))
+    }).collect()
+}
+
+/// The arguments applying to an item nested within a `#[with]`-annotated
+/// `impl`, `trait` or `mod`: the outer ones, overridden by those spelled out
+/// in its own (removed) `#[with(...)]` attribute, if any; or `None` for a
+/// `#[with(skip)]` opt-out.
+fn inner_attrs<'attrs> (
+    outer_with_attrs: &'attrs Attrs,
+    attrs: &'_ mut Vec<Attribute>,
+    storage: &'attrs mut Option<Attrs>,
+    encountered_error: &'_ mut Option<Error>,
+) -> Option<&'attrs Attrs>
+{
+    mod kw {
+        ::syn::custom_keyword!(skip);
+    }
+    let attr = match take_with_attr(attrs) {
+        | Ok(Some(attr)) => attr,
+        | Ok(None) => return Some(outer_with_attrs),
+        | Err(err) => {
+            encountered_error.push_error(err);
+            return Some(outer_with_attrs);
+        },
+    };
+    if attr.parse_args::<kw::skip>().is_ok() {
+        return None;
+    }
+    if attr.tokens.is_empty() {
+        // A bare `#[with]`: nothing to override.
+        return Some(outer_with_attrs);
+    }
+    let parsed_attrs = attr.parse_args_with(|input: ParseStream<'_>| {
+        Attrs::parse_over(outer_with_attrs.clone(), input)
+    });
+    match parsed_attrs {
+        | Ok(attrs) => Some(storage.get_or_insert(attrs)),
+        | Err(err) => {
+            encountered_error.push_error(err);
+            Some(outer_with_attrs)
+        },
+    }
+}
+
+/// Removes the (inner) `#[with(...)]` attribute from a list of attributes,
+/// returning it, if any.
+fn take_with_attr (attrs: &'_ mut Vec<Attribute>)
+  -> Result<Option<Attribute>>
+{
+    let mut attr = None;
+    let mut err = None;
+    attrs.retain(|cur_attr| if cur_attr.path.is_ident("with") {
+        let prev = attr.replace(cur_attr.clone());
+        if let Some(prev) = prev {
+            err = Some(Error::new_spanned(prev,
+                "Duplicate `#[with]` attribute",
+            ));
+        }
+        false // remove the attribute
+    } else {
+        true
+    });
+    if let Some(err) = err { return Err(err); }
+    Ok(attr)
+}
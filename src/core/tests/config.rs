@@ -0,0 +1,117 @@
+//! Kept in its own test binary (and `#[test]`), since it sets
+//! `CARGO_MANIFEST_DIR`.
+
+use ::quote::{
+    quote,
+};
+use ::std::{
+    env,
+    fs,
+};
+
+use ::core::ops::Not as _;
+
+use ::with_locals_core::{
+    expand,
+};
+
+fn with_manifest (name: &'_ str, metadata: &'_ str)
+{
+    with_raw_manifest(name, &format!("\
+        [package.metadata.with_locals]\n\
+        {}\n\
+        \n\
+        [dependencies]\n\
+        with_locals = \"0.3\"\n\
+    ", metadata));
+}
+
+fn with_raw_manifest (name: &'_ str, manifest: &'_ str)
+{
+    let dir = env::temp_dir().join(format!(
+        "with_locals-core-tests-{}-{}", name, ::std::process::id(),
+    ));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("Cargo.toml"), format!("\
+        [package]\n\
+        name = \"{}\"\n\
+        \n\
+        {}\
+    ", name, manifest)).unwrap();
+    env::set_var("CARGO_MANIFEST_DIR", dir);
+}
+
+#[test]
+fn cargo_toml_defaults ()
+{
+    with_manifest("valid", "\
+        lifetime = \"local\" # The special lifetime.\n\
+        name = 'scoped_{}'\n\
+        dyn_safe = true\n\
+    ");
+    let expansion = expand(quote!(), quote! {
+        fn hex (n: u32) -> &'local dyn ::core::fmt::Display
+        {
+            #[with]
+            let s = to_str(n);
+            &format_args!("{:#x}", s)
+        }
+    }).unwrap().to_string();
+    assert!(expansion.contains("fn scoped_hex"));
+    assert!(expansion.contains("scoped_to_str"));
+    assert!(expansion.contains("ContinuationReturn"));
+
+    // Per-item arguments override the crate-wide defaults.
+    let expansion = expand(quote!('ref, dyn_safe = false), quote! {
+        fn hex (n: u32) -> &'ref dyn ::core::fmt::Display
+        {
+            &format_args!("{:#x}", n)
+        }
+    }).unwrap().to_string();
+    assert!(expansion.contains("fn scoped_hex"));
+    assert!(expansion.contains("ContinuationReturn").not());
+
+    with_manifest("invalid", "recursive = \"yes\"");
+    let err = expand(quote!('local), quote! {
+        fn hex (n: u32) -> &'local u32 { &n }
+    }).unwrap_err();
+    assert!(err.to_string().contains("expected `true` or `false` for `recursive`"));
+
+    with_manifest("unknown", "lifetimes = \"local\"");
+    let err = expand(quote!('local), quote! {
+        fn hex (n: u32) -> &'local u32 { &n }
+    }).unwrap_err();
+    assert!(err.to_string().contains("unknown key `lifetimes`"));
+
+    // Any TOML spelling of the table is understood.
+    for (i, manifest) in [
+        "[ package.metadata.with_locals ] # Comment.\nname = 'scoped_{}'\n",
+        "[package.metadata]\nwith_locals.name = 'scoped_{}'\n",
+        "[package.metadata]\nwith_locals = { name = 'scoped_{}' }\n",
+        "[package.metadata.\"with_locals\"]\n\"name\" = \"scoped_{}\"\n",
+    ].iter().enumerate()
+    {
+        with_raw_manifest(&format!("spelling-{}", i), manifest);
+        let expansion = expand(quote!('ref), quote! {
+            fn hex (n: u32) -> &'ref u32 { &n }
+        }).unwrap().to_string();
+        assert!(expansion.contains("fn scoped_hex"), "{}", manifest);
+    }
+
+    // The `name` pattern applies to the call sites as well, so calling a
+    // with-function of another crate (using the default name) requires saying
+    // so at the call site.
+    with_manifest("call-sites", "name = 'scoped_{}'");
+    let expansion = expand(quote!('ref), quote! {
+        fn hex (n: u32) -> &'ref u32
+        {
+            #[with]
+            let a = local_to_str(n);
+            #[with(name = "with_{}")]
+            let b = foreign_to_str(n);
+            &n
+        }
+    }).unwrap().to_string();
+    assert!(expansion.contains("scoped_local_to_str"));
+    assert!(expansion.contains("with_foreign_to_str"));
+}
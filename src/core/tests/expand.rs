@@ -0,0 +1,266 @@
+// The `self: &'_ Self` spelling is deliberate.
+#![allow(clippy::needless_arbitrary_self_type)]
+
+use ::proc_macro2::{
+    TokenStream as TokenStream2,
+};
+use ::quote::{
+    quote,
+    ToTokens,
+};
+use ::syn::{*,
+    visit_mut::{self, VisitMut},
+};
+
+use ::core::ops::Not as _;
+use ::with_locals_core::{
+    expand,
+    expand_fn,
+    expand_recovering,
+    Attrs,
+};
+
+/// Names of the `fn`s and of the called functions / methods in the expansion.
+#[derive(Default)]
+struct Names {
+    fns: Vec<String>,
+    calls: Vec<String>,
+}
+
+impl VisitMut for Names {
+    fn visit_signature_mut (
+        self: &'_ mut Self,
+        sig: &'_ mut Signature,
+    )
+    {
+        self.fns.push(sig.ident.to_string());
+        visit_mut::visit_signature_mut(self, sig);
+    }
+
+    fn visit_expr_call_mut (
+        self: &'_ mut Self,
+        call: &'_ mut ExprCall,
+    )
+    {
+        if let Expr::Path(ref path) = *call.func {
+            let last = path.path.segments.last().unwrap();
+            self.calls.push(last.ident.to_string());
+        }
+        visit_mut::visit_expr_call_mut(self, call);
+    }
+
+    fn visit_expr_method_call_mut (
+        self: &'_ mut Self,
+        call: &'_ mut ExprMethodCall,
+    )
+    {
+        self.calls.push(call.method.to_string());
+        visit_mut::visit_expr_method_call_mut(self, call);
+    }
+}
+
+fn names (expansion: TokenStream2)
+  -> Names
+{
+    let mut file: File = parse2(expansion).expect("expansion is a valid file");
+    let mut names = Names::default();
+    names.visit_file_mut(&mut file);
+    names
+}
+
+#[test]
+fn returning_locals ()
+{
+    let expansion = expand(
+        quote!('local),
+        quote! {
+            fn hex (n: u32) -> &'local dyn ::core::fmt::Display
+            {
+                &format_args!("{:#x}", n)
+            }
+        },
+    ).unwrap();
+    let names = names(expansion);
+    assert_eq!(names.fns, ["with_hex"]);
+}
+
+#[test]
+fn mod_fns_get_a_shadow ()
+{
+    let expansion = expand(
+        quote!('local),
+        quote! {
+            mod views {
+                fn hex (n: u32) -> &'local dyn ::core::fmt::Display
+                {
+                    &format_args!("{:#x}", n)
+                }
+            }
+        },
+    ).unwrap();
+    let names = names(expansion);
+    // The original name only remains as the hidden (deprecated) shadow `fn`.
+    assert_eq!(names.fns, ["with_hex", "hex"]);
+}
+
+#[test]
+fn inner_attrs_override_the_outer_ones ()
+{
+    let expansion = expand(
+        quote!('local, name = "scoped_{}"),
+        quote! {
+            impl Foo {
+                #[with(dyn_safe = true)]
+                fn name (self: &'_ Self) -> &'local str
+                {
+                    "foo"
+                }
+
+                fn len (self: &'_ Self) -> &'local usize
+                {
+                    &3
+                }
+            }
+        },
+    ).unwrap().to_string();
+    // Only `dyn_safe` is changed: the lifetime and name pattern are kept.
+    let item: ItemImpl = parse_str(&expansion).unwrap();
+    let names = names(item.into_token_stream());
+    assert_eq!(names.fns, ["scoped_name", "scoped_len"]);
+    let (name, len) = expansion.split_at(expansion.find("fn scoped_len").unwrap());
+    assert!(name.contains("ContinuationReturn"));
+    assert!(len.contains("ContinuationReturn").not());
+}
+
+#[test]
+fn let_bindings ()
+{
+    let attrs: Attrs = parse2(quote!()).unwrap();
+    let expansion = expand_fn(&attrs, parse_quote! {
+        fn main ()
+        {
+            #[with]
+            let s = hex(66);
+            #[with]
+            let n = s.len();
+            println!("{} {}", s, n);
+        }
+    }).unwrap();
+    let names = names(expansion);
+    assert_eq!(names.fns, ["main"]);
+    assert!(names.calls.contains(&"with_hex".into()));
+    assert!(names.calls.contains(&"with_len".into()));
+    assert!(names.calls.contains(&"hex".into()).not());
+}
+
+#[test]
+fn cfg_with_lets_do_not_repeat_their_continuation ()
+{
+    let attrs: Attrs = parse2(quote!()).unwrap();
+    let expansion = expand_fn(&attrs, parse_quote! {
+        fn main ()
+        {
+            #[cfg(feature = "a")]
+            #[with]
+            let a = hex(1);
+            #[cfg(feature = "b")]
+            #[with]
+            let b = hex(2);
+            #[cfg(feature = "c")]
+            #[with]
+            let c = hex(3);
+            innermost();
+        }
+    }).unwrap().to_string();
+    assert_eq!(expansion.matches("innermost").count(), 1);
+    assert_eq!(expansion.matches("with_hex").count(), 3);
+}
+
+#[test]
+fn methods_are_expanded_in_place ()
+{
+    let expansion = expand(
+        quote!('local),
+        quote! {
+            impl Foo {
+                fn name (self: &'_ Self) -> &'local str
+                {
+                    "foo"
+                }
+            }
+        },
+    ).unwrap();
+    let item: ItemImpl = parse2(expansion).unwrap();
+    let names = names(item.into_token_stream());
+    assert_eq!(names.fns, ["with_name"]);
+}
+
+#[test]
+fn errors ()
+{
+    let err = expand(quote!(), quote!( struct Foo; )).unwrap_err();
+    assert!(err.to_string().contains("can only be applied to"));
+
+    let err = expand(quote!(dyn_safe = 42), quote!( fn foo () {} ));
+    assert!(err.is_err());
+
+    let err = expand_fn(&parse_quote!(), parse_quote! {
+        fn foo ()
+        {
+            #[with]
+            let x;
+        }
+    }).unwrap_err();
+    assert_eq!(err.to_string(), "Missing expression");
+}
+
+#[test]
+fn best_effort_expansion_on_error ()
+{
+    let expansion = expand_recovering(
+        quote!('local, dyn_safe = 42),
+        quote! {
+            fn hex (n: u32) -> &'local dyn ::core::fmt::Display
+            {
+                #[with]
+                let _incomplete;
+                &format_args!("{:#x}", n)
+            }
+        },
+    );
+    let file: File = parse2(expansion).unwrap();
+    let errors =
+        file.items
+            .iter()
+            .filter(|item| matches!(item,
+                Item::Macro(ItemMacro { mac, .. }) if mac.path.is_ident("compile_error")
+            ))
+            .count()
+    ;
+    assert_eq!(errors, 2);
+    let names = names(file.into_token_stream());
+    assert!(names.fns.contains(&"with_hex".into()));
+}
+
+#[test]
+fn custom_crate_path ()
+{
+    let expansion = expand(
+        quote!('local, dyn_safe = true, crate = ::facade::with_locals),
+        quote! {
+            fn hex (n: u32) -> &'local dyn ::core::fmt::Display
+            {
+                #[with]
+                let s = hex(n - 1);
+                &format_args!("{}", s)
+            }
+        },
+    ).unwrap().to_string();
+    assert!(expansion.contains(":: facade :: with_locals :: __"));
+    assert!(expansion.contains(":: facade :: with_locals :: dyn_safe"));
+    // Every mention of the crate goes through the custom path.
+    assert_eq!(
+        expansion.matches(":: with_locals ::").count(),
+        expansion.matches(":: facade :: with_locals ::").count(),
+    );
+}
@@ -0,0 +1,50 @@
+//! Kept in its own test binary (and `#[test]`), since it sets the env vars
+//! the `expand-macros` feature reads.
+
+#![cfg(feature = "expand-macros")]
+
+use ::quote::{
+    quote,
+};
+use ::std::{
+    env,
+    fs,
+};
+
+use ::with_locals_core::{
+    expand,
+};
+
+#[test]
+fn same_named_items_get_distinct_files ()
+{
+    let dir = env::temp_dir().join(format!(
+        "with_locals-core-expand-macros-{}", ::std::process::id(),
+    ));
+    env::set_var("WITH_LOCALS_EXPAND_DIR", &dir);
+    env::set_var("CARGO_CRATE_NAME", "krate");
+    let impl_ = |method: &str| expand(quote!('local), quote! {
+        impl Foo {
+            fn hex (self: &'_ Self, n: u32) -> &'local str
+            {
+                &format!(#method, n)
+            }
+        }
+    }).unwrap();
+    impl_("{:#x}");
+    impl_("{:#X}");
+    expand(quote!('local), quote! {
+        impl ToStr for Foo<u8> {
+            fn to_str (self: &'_ Self) -> &'local str
+            {
+                &format!("{:?}", self)
+            }
+        }
+    }).unwrap();
+
+    let read = |file: &str| fs::read_to_string(dir.join("krate").join(file)).unwrap();
+    assert!(read("impl_Foo.rs").contains("{:#x}"));
+    assert!(read("impl_Foo.2.rs").contains("{:#X}"));
+    assert!(read("impl_ToStr_for_Foo_u8_.rs").contains("fn with_to_str"));
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,396 @@
+use super::*;
+
+pub(in super) use wrap_statements_inside_closure_body as f;
+
+pub(in crate)
+struct Ret {
+    pub(in crate)
+    closure_body: TokenStream2,
+
+    /// The `ControlFlow<...>` type the `closure_body` evaluates to (with `_`
+    /// for the parts left to type inference).
+    pub(in crate)
+    ty: TokenStream2,
+
+    pub(in crate)
+    wrap_ret: TokenStream2,
+
+    pub(in crate)
+    wrap_break: TokenStream2,
+
+    pub(in crate)
+    wrap_continue: TokenStream2,
+}
+
+/// The frontend crate exposes the following helpers:
+///
+/// ```rust
+/// enum ControlFlow<Eval, Return, Break, Continue> {
+///     Eval(Eval),
+///     EarlyReturn(Return),
+///     Break(Break),
+///     Continue(Continue),
+/// }
+///
+/// enum Unreachable {}
+/// ```
+///
+/// See `src/lib.rs` for the actual code.
+pub(in super)
+fn wrap_statements_inside_closure_body (
+    mut stmts: ::std::collections::VecDeque<Stmt>,
+    CallSiteAttrs { ret, return_, break_, .. }: &'_ CallSiteAttrs,
+    encountered_error: &'_ mut Option<Error>,
+) -> Ret
+{
+    #![allow(nonstandard_style)]
+
+    proc_macro_use! {
+        use $krate::{
+            ControlFlow,
+        };
+    }
+
+    /// Required because you can't always `break ()` where you can `break`
+    /// (_e.g._, `for` loops) ... -___-'
+    enum BreakKind {
+        WithValue(Span, Span),
+        Alone(Span),
+    }
+
+    let mut visitor = {
+        struct Visitor<'__> {
+            encountered_error: &'__ mut Option<Error>,
+            // We need to keep track of the variants used in the returned enum
+            // since those unused won't have type inference kicking in,
+            // and will thus need explicit `Void` type annotations.
+            // (The span of the first usage is the one of the generated
+            // handler, for type errors to point at it.)
+            explicit_return: Option<Span>,
+            break_kind: Option<BreakKind>,
+            has_continue: Option<Span>,
+            within_loop: bool,
+        }
+        impl VisitMut for Visitor<'_> {
+            fn visit_item_mut (
+                self: &'_ mut Self,
+                item: &'_ mut Item,
+            )
+            {
+                // Stop recursing (but for statement macros).
+                if let Item::Macro(ItemMacro { ident: None, ref mut mac, .. }) = *item {
+                    self.visit_macro_mut(mac);
+                }
+            }
+
+            fn visit_macro_mut (
+                self: &'_ mut Self,
+                mac: &'_ mut Macro,
+            )
+            {
+                visit_macro_args_mut(self, mac);
+            }
+
+            fn visit_expr_mut (
+                self: &'_ mut Self,
+                expr: &'_ mut Expr,
+            )
+            {
+                match *expr {
+                    | Expr::Return(ref mut expr_return) => {
+                        let return_span = expr_return.return_token.span;
+                        self.explicit_return.get_or_insert(return_span);
+                        proc_macro_use! {
+                            #[spanned(return_span)]
+                            use $krate::{ControlFlow};
+                        }
+                        let span = spanned_at(return_span);
+                        let storage;
+                        let returned_value =
+                            if let Some(ref it) = expr_return.expr {
+                                &**it
+                            } else {
+                                storage = parse_quote_spanned! {span=>
+                                    ()
+                                };
+                                &storage
+                            }
+                        ;
+                        expr_return.expr = Some(parse_quote_spanned! {span=>
+                            #ControlFlow::EarlyReturn(#returned_value)
+                        });
+                    },
+
+                    | Expr::Continue(ref expr_continue)
+                        if self.within_loop.not()
+                    => {
+                        let continue_span = expr_continue.continue_token.span;
+                        self.has_continue.get_or_insert(continue_span);
+                        if let Some(ref label) = expr_continue.label {
+                            self.encountered_error.push_error(Error::new(
+                                label.span(),
+                                "`#[with]` does not support labelled `continue`s",
+                            ));
+                            // Keep going as if it were unlabelled.
+                        }
+                        proc_macro_use! {
+                            #[spanned(continue_span)]
+                            use $krate::{ControlFlow};
+                        }
+                        let span = spanned_at(continue_span);
+                        *expr = parse_quote_spanned! {span=>
+                            return #ControlFlow::Continue(())
+                        };
+                    },
+
+                    | Expr::Break(ref expr_break)
+                        if self.within_loop.not()
+                    => {
+                        let break_span = expr_break.break_token.span;
+                        if let Some(ref label) = expr_break.label {
+                            self.encountered_error.push_error(Error::new(
+                                label.span(),
+                                "`#[with]` does not support labelled `break`s",
+                            ));
+                            // Keep going as if it were unlabelled.
+                        }
+                        let storage;
+                        let broken_value =
+                            if let Some(ref break_value) = expr_break.expr {
+                                self.break_kind.replace(BreakKind::WithValue(
+                                    expr_break.break_token.span(),
+                                    break_value.span(),
+                                ));
+                                &**break_value
+                            } else {
+                                self.break_kind = Some(BreakKind::Alone(break_span));
+                                storage = parse_quote_spanned! {break_span=>
+                                    ()
+                                };
+                                &storage
+                            }
+                        ;
+                        proc_macro_use! {
+                            #[spanned(break_span)]
+                            use $krate::{ControlFlow};
+                        }
+                        let span = spanned_at(break_span);
+                        *expr = parse_quote_spanned! {span=>
+                            return #ControlFlow::Break(#broken_value)
+                        };
+                    },
+
+                    | Expr::Try(ref mut expr_try) => {
+                        let question_span = expr_try.question_token.spans[0];
+                        self.explicit_return.get_or_insert(question_span);
+                        let matchee = &mut expr_try.expr;
+                        // /!\ sub-recurse *before* the transformation, since
+                        // we do generate an *inner* `return` expression:
+                        self.visit_expr_mut(matchee);
+                        proc_macro_use! {
+                            #[spanned(question_span)]
+                            use $krate::{
+                                ControlFlow,
+                                Break_, Continue_,
+                                FromResidual,
+                                Try,
+                            };
+                        }
+                        let span = spanned_at(question_span);
+                        *expr = parse_quote_spanned! {span=>
+                            match #matchee { it => match #Try::branch(it) {
+                                | #Continue_(it) => it,
+                                | #Break_(residual) => {
+                                    return #ControlFlow::EarlyReturn(
+                                        #FromResidual::from_residual(residual)
+                                    );
+                                },
+                            }}
+                        };
+                        // do not subrecurse now
+                        return;
+                    },
+
+                    | Expr::ForLoop(_)
+                    | Expr::Loop(_)
+                    | Expr::While(_)
+                    => {
+                        self.within_loop = (
+                            ::core::mem::replace(&mut self.within_loop, true),
+                            // sub-recurse
+                            visit_mut::visit_expr_mut(self, expr),
+                        ).0;
+                        // (already sub-recursed)
+                        return;
+                    },
+
+                    | Expr::Async(_)
+                    | Expr::Closure(_)
+                    => {
+                        // skip sub-recursing
+                        return;
+                    },
+
+                    | _ => {},
+                }
+                // sub-recurse
+                visit_mut::visit_expr_mut(self, expr);
+            }
+        }
+        Visitor {
+            encountered_error,
+            explicit_return: None,
+            break_kind: None,
+            has_continue: None,
+            within_loop: false,
+        }
+    };
+    stmts.iter_mut().for_each(|stmt| {
+        visitor.visit_stmt_mut(stmt)
+    });
+    let stmts = stmts.into_iter();
+
+    fn ty_and_handler (
+        first_usage: Option<Span>,
+        wrapper: impl FnOnce(Span) -> TokenStream2,
+    ) -> (
+            TokenStream2,
+            TokenStream2,
+        )
+    {
+        proc_macro_use! {
+            use $krate::{Unreachable};
+        }
+        if let Some(span) = first_usage {
+            (
+                quote! {
+                    _
+                },
+                wrapper(spanned_at(span)),
+            )
+        } else {
+            (
+                quote! {
+                    #Unreachable
+                },
+                quote! {
+                    {
+                        let unreachable = it;
+                        match unreachable {}
+                    }
+                },
+            )
+        }
+    }
+
+    /// An explicit `return = <type>` (or `break = <type>`) only makes sense
+    /// when the continuation does `return` (or `break`).
+    fn explicit_ty (
+        ty: &'_ Option<Type>,
+        inferred: TokenStream2,
+        is_used: bool,
+        what: &'_ str,
+        encountered_error: &'_ mut Option<Error>,
+    ) -> TokenStream2
+    {
+        match *ty {
+            | Some(ref ty) if is_used => ty.to_token_stream(),
+            | Some(ref ty) => {
+                encountered_error.push_error(Error::new_spanned(ty, format!(
+                    "the statements that follow this `#[with] let` do not {}",
+                    what,
+                )));
+                inferred
+            },
+            | None => inferred,
+        }
+    }
+
+    let Eval = ret.as_ref().map_or_else(|| quote!( _ ), ToTokens::to_token_stream);
+    let (Return, wrap_ret) = ty_and_handler(
+        visitor.explicit_return,
+        |span| quote_spanned! {span=>
+            return it
+        },
+    );
+    let Return = explicit_ty(
+        return_,
+        Return,
+        visitor.explicit_return.is_some(),
+        "`return` (nor use `?`)",
+        visitor.encountered_error,
+    );
+    let has_break = visitor.break_kind.is_some();
+    let (Break, wrap_break) = match visitor.break_kind {
+        | None => (
+            {
+                proc_macro_use! {
+                    use $krate::{Unreachable};
+                }
+                Unreachable
+            },
+            quote! {
+                {
+                    let unreachable = it;
+                    match unreachable {}
+                }
+            },
+        ),
+        | Some(break_kind) => (
+            quote! { _ },
+            match break_kind {
+                | BreakKind::WithValue(start, end) => {
+                    let break_ = quote_spanned!(start=> break );
+                    let it = quote_spanned!(end=> it );
+                    quote! {
+                        #break_ #it
+                    }
+                },
+                | BreakKind::Alone(span) => {
+                    let span = spanned_at(span);
+                    quote_spanned! {span=>
+                        {
+                            let () = it;
+                            break;
+                        }
+                    }
+                },
+            },
+        ),
+    };
+    let Break = explicit_ty(
+        break_,
+        Break,
+        has_break,
+        "`break`",
+        visitor.encountered_error,
+    );
+    let (Continue, wrap_continue) = ty_and_handler(
+        visitor.has_continue,
+        |span| quote_spanned! {span=>
+            { let () = it; continue }
+        },
+    );
+    Ret {
+        // The user statements are kept out of the scope of the `allow`, so
+        // that only the (possibly unreachable) generated wrapping is exempt.
+        // (Bar the clippy lint about diverging `let` initializers, which
+        // statements such as a `return;` trigger once in this position.)
+        closure_body: quote! {{
+            #[allow(clippy::diverging_sub_expression)]
+            let __with_locals_eval__ = {
+                #(#stmts)*
+            };
+            #[allow(unreachable_code)]
+            #ControlFlow::<#Eval, #Return, #Break, #Continue>::Eval(
+                __with_locals_eval__
+            )
+        }},
+        ty: quote!(
+            #ControlFlow<#Eval, #Return, #Break, #Continue>
+        ),
+        wrap_ret,
+        wrap_break,
+        wrap_continue,
+    }
+}
@@ -0,0 +1,175 @@
+//! For advanced users that hand-write the continuations given to `with_`
+//! functions, while still wanting `return`, `break` and `continue` to "cross"
+//! the closure boundary, like they do within `#[with]` code.
+//!
+//! The continuation is to return a [`ControlFlow`], using [`cps_return!`],
+//! [`cps_break!`] and [`cps_continue!`] where it would have used the
+//! respective control flow statements, and [`ControlFlow::Eval`] otherwise.
+//! The call to the `with_` function is then to be wrapped in a
+//! [`propagate!`], which re-issues the captured control flow in the caller.
+//!
+//! ```rust
+//! use ::core::fmt::Display;
+//! use ::with_locals::{cps::ControlFlow, cps_break, cps_continue, cps_return, propagate, with};
+//!
+//! #[with('local)]
+//! fn hex (n: u32) -> &'local dyn Display
+//! {
+//!     &format_args!("{:#x}", n)
+//! }
+//!
+//! fn first_hex_with_a_letter (ns: &'_ [u32]) -> Option<String>
+//! {
+//!     for &n in ns {
+//!         let s = propagate!(loop, with_hex(n, |s| {
+//!             if n == 0 {
+//!                 cps_break!();
+//!             }
+//!             let s = s.to_string();
+//!             if s.bytes().all(|b| b.is_ascii_digit() || b == b'x') {
+//!                 cps_continue!();
+//!             }
+//!             ControlFlow::Eval(s)
+//!         }));
+//!         return Some(s);
+//!     }
+//!     None
+//! }
+//!
+//! fn len (n: u32) -> usize
+//! {
+//!     propagate!(with_hex(n, |s| {
+//!         if n == 0 {
+//!             cps_return!(0);
+//!         }
+//!         ControlFlow::Eval(s.to_string().len())
+//!     }))
+//! }
+//!
+//! assert_eq!(first_hex_with_a_letter(&[1, 9, 10, 11]).as_deref(), Some("0xa"));
+//! assert_eq!(first_hex_with_a_letter(&[1, 0, 10]), None);
+//! assert_eq!(len(0), 0);
+//! assert_eq!(len(66), "0x42".len());
+//! ```
+//!
+//! [`cps_return!`]: crate::cps_return!
+//! [`cps_break!`]: crate::cps_break!
+//! [`cps_continue!`]: crate::cps_continue!
+//! [`propagate!`]: crate::propagate!
+
+/// What a continuation evaluated to, or which control flow statement it
+/// "called" (to be re-issued by the caller, _e.g._, through [`propagate!`]).
+///
+/// This is the very same type that `#[with] let` statements expand to.
+///
+/// [`propagate!`]: crate::propagate!
+pub
+enum ControlFlow<Eval, Return, Break, Continue> {
+    /// Classic block evaluation.
+    Eval(Eval),
+
+    /// Must `return` the value early.
+    EarlyReturn(Return),
+
+    /// Must `break` with the value.
+    Break(Break),
+
+    /// Must `continue`.
+    Continue(Continue),
+}
+
+/// `return` from the function calling the `with_` function, from within the
+/// continuation (see the [`cps`](crate::cps) module).
+///
+/// `cps_return!()` is the same as `cps_return!(())`.
+#[macro_export]
+macro_rules! cps_return {
+    () => (
+        $crate::cps_return!(())
+    );
+
+    ( $value:expr $(,)? ) => (
+        return $crate::cps::ControlFlow::EarlyReturn($value)
+    );
+}
+
+/// `break` out of the loop the `with_` function is called within, from within
+/// the continuation (see the [`cps`](crate::cps) module).
+///
+/// `cps_break!(<value>)` breaks with a value, and thus requires the call to be
+/// wrapped in a `propagate!(break, ...)` (and be within a `loop { ... }`).
+/// `cps_break!()` is the same as `cps_break!(())`.
+///
+/// Labels are not supported (as with `#[with]` code).
+#[macro_export]
+macro_rules! cps_break {
+    () => (
+        $crate::cps_break!(())
+    );
+
+    ( $value:expr $(,)? ) => (
+        return $crate::cps::ControlFlow::Break($value)
+    );
+}
+
+/// `continue` the loop the `with_` function is called within, from within the
+/// continuation (see the [`cps`](crate::cps) module).
+#[macro_export]
+macro_rules! cps_continue {() => (
+    return $crate::cps::ControlFlow::Continue(())
+)}
+
+/// Evaluates to the [`ControlFlow::Eval`] value of the given expression, or
+/// re-issues the control flow its continuation "called".
+///
+///   - `propagate!(<expr>)` only supports [`cps_return!`];
+///
+///   - `propagate!(loop, <expr>)`, to be used within a loop, also supports
+///     [`cps_break!`]`()` and [`cps_continue!`];
+///
+///   - `propagate!(break, <expr>)`, to be used within a `loop { ... }`, also
+///     supports [`cps_break!`]`(<value>)` (and [`cps_continue!`]), the value
+///     being the one the `loop` evaluates to. This is a separate form since
+///     `for` and `while` loops can't `break` with a value, not even `()`.
+///
+/// The re-issued `break`s and `continue`s apply to the innermost enclosing
+/// loop: labels are not supported.
+///
+/// See the [`cps`](crate::cps) module for an example.
+#[macro_export]
+macro_rules! propagate {
+    ( loop, $expr:expr $(,)? ) => (
+        match $expr {
+            | $crate::cps::ControlFlow::Eval(it) => it,
+            | $crate::cps::ControlFlow::EarlyReturn(it) => return it,
+            | $crate::cps::ControlFlow::Break(()) => break,
+            | $crate::cps::ControlFlow::Continue(()) => continue,
+        }
+    );
+
+    ( break, $expr:expr $(,)? ) => (
+        match $expr {
+            | $crate::cps::ControlFlow::Eval(it) => it,
+            | $crate::cps::ControlFlow::EarlyReturn(it) => return it,
+            | $crate::cps::ControlFlow::Break(it) => break it,
+            | $crate::cps::ControlFlow::Continue(()) => continue,
+        }
+    );
+
+    ( $expr:expr $(,)? ) => (
+        match $expr { it => {
+            let it: $crate::cps::ControlFlow<
+                _,
+                _,
+                $crate::__::Unreachable,
+                $crate::__::Unreachable,
+            > = it;
+            match it {
+                | $crate::cps::ControlFlow::Eval(it) => it,
+                | $crate::cps::ControlFlow::EarlyReturn(it) => return it,
+                | $crate::cps::ControlFlow::Break(unreachable) => match unreachable {},
+                | $crate::cps::ControlFlow::Continue(unreachable) => match unreachable {},
+            }
+        }}
+    );
+}
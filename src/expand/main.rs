@@ -0,0 +1,855 @@
+//! `with-locals-expand [--in-place] [--filter <pattern>] [--manifest-path <Cargo.toml>] <file.rs>`
+//!
+//! Applies the transformations of the `#[with]` attribute (be it spelled
+//! `#[with]`, `#[with_locals::with]`, or `#[crate::with]`) to every annotated
+//! item of a source file, as well as those of the `with_block!` macro, and
+//! either prints the expansions to stdout, or rewrites the file in place
+//! (_e.g._, to audit or to migrate away from the sugar).
+//!
+//! Only the annotated items (and `with_block!` invocations) are rewritten: the
+//! rest of the file, comments included, is left untouched.
+//!
+//! Note that the rewritten items lose their (non-doc) comments, and refer to
+//! the `#[doc(hidden)]` internals of `with_locals` (`::with_locals::__::*`),
+//! which are exempt from semver guarantees: code migrated with `--in-place`
+//! thus needs to keep depending on the exact same version of `with_locals`.
+
+#![allow(nonstandard_style)]
+// The `self: &'_ Self` spelling is deliberate.
+#![allow(clippy::needless_arbitrary_self_type)]
+
+use ::proc_macro2::{
+    LineColumn,
+    TokenStream as TokenStream2,
+    TokenTree,
+};
+use ::quote::{
+    quote,
+    ToTokens,
+};
+use ::syn::{*,
+    visit_mut::{self, VisitMut},
+    Result,
+};
+
+use ::std::{
+    env,
+    fs,
+    mem,
+    ops::Not as _,
+    path::PathBuf,
+    process,
+};
+
+const USAGE: &str = "\
+Usage: with-locals-expand [options] <file.rs>
+
+Applies the `#[with]` transformations to every annotated item of <file.rs>,
+as well as to every `with_block!` invocation.
+
+Options:
+  -i, --in-place          Rewrite the file instead of printing the expansions.
+                          Beware that the rewritten items lose their comments,
+                          and refer to `::with_locals::__::*` internals, which
+                          may change in any release: pin the exact version of
+                          `with_locals` (`with_locals = \"=x.y.z\"`).
+  -f, --filter <pattern>  Only expand the items whose name contains <pattern>
+                          (`with_block!` invocations being named `with_block`).
+  -m, --manifest-path <Cargo.toml>
+                          The manifest whose `[package.metadata.with_locals]`
+                          defaults apply (by default, the closest one to the
+                          file).
+  -h, --help              Print this message.
+";
+
+struct Args {
+    in_place: bool,
+    filter: Option<String>,
+    manifest_path: Option<PathBuf>,
+    path: PathBuf,
+}
+
+fn parse_args ()
+  -> ::core::result::Result<Args, String>
+{
+    let mut in_place = false;
+    let mut filter = None;
+    let mut manifest_path = None;
+    let mut path = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match &*arg {
+            | "-h" | "--help" => {
+                print!("{}", USAGE);
+                process::exit(0);
+            },
+            | "-i" | "--in-place" => in_place = true,
+            | "-f" | "--filter" => {
+                filter = Some(args.next().ok_or("missing `--filter` pattern")?);
+            },
+            | "-m" | "--manifest-path" => {
+                manifest_path = Some(PathBuf::from(
+                    args.next().ok_or("missing `--manifest-path` path")?
+                ));
+            },
+            | _ if arg.starts_with('-') => {
+                return Err(format!("unknown option `{}`", arg));
+            },
+            | _ => if path.replace(PathBuf::from(arg)).is_some() {
+                return Err("expected a single file".into());
+            },
+        }
+    }
+    Ok(Args {
+        in_place,
+        filter,
+        manifest_path,
+        path: path.ok_or("missing <file.rs>")?,
+    })
+}
+
+fn main ()
+{
+    if let Err(err) = try_main() {
+        eprintln!("error: {}\n\n{}", err, USAGE);
+        process::exit(1);
+    }
+}
+
+fn try_main ()
+  -> ::core::result::Result<(), String>
+{
+    let Args { in_place, ref filter, manifest_path, ref path } = parse_args()?;
+    let source =
+        fs::read_to_string(path)
+            .map_err(|err| format!("failed to read `{}`: {}", path.display(), err))?
+    ;
+    let display_error = |err: Error| {
+        let LineColumn { line, column } = err.span().start();
+        format!("{}:{}:{}: {}", path.display(), line, column + 1, err)
+    };
+    // Honor the `[package.metadata.with_locals]` defaults of the crate the
+    // file belongs to, as the attribute would.
+    let manifest_path = manifest_path.or_else(|| {
+        fs::canonicalize(path).ok()?
+            .ancestors()
+            .skip(1)
+            .map(|dir| dir.join("Cargo.toml"))
+            .find(|manifest| manifest.is_file())
+    });
+    let mut file = parse_file(&source).map_err(display_error)?;
+    let mut replacements = vec![];
+    let mut expander = Expander {
+        filter: filter.as_deref(),
+        replacements: Some(&mut replacements),
+        scope: None,
+        within_with: false,
+        warnings: vec![],
+        encountered_error: None,
+    };
+    match manifest_path {
+        | Some(ref manifest_path) => {
+            ::with_locals_core::with_manifest_path(manifest_path, || {
+                expander.visit_file_mut(&mut file);
+            });
+        },
+        | None => expander.visit_file_mut(&mut file),
+    }
+    for (LineColumn { line, column }, warning) in mem::take(&mut expander.warnings) {
+        eprintln!("warning: {}:{}:{}: {}", path.display(), line, column + 1, warning);
+    }
+    if let Some(err) = expander.encountered_error {
+        return Err(display_error(err));
+    }
+    if in_place {
+        let mut source = source;
+        // Splice from the end so that the earlier offsets remain valid.
+        for Replacement { name, start, end, code } in replacements.iter().rev() {
+            let indent = " ".repeat(start.column);
+            let code = code.lines().collect::<Vec<_>>().join(&format!("\n{}", indent));
+            let (line, column) = (start.line, start.column);
+            let start = byte_offset(&source, *start);
+            let end = byte_offset(&source, *end);
+            if has_comments(&source[start .. end]) {
+                eprintln!(
+                    "warning: {}:{}:{}: the comments within `{}` have been dropped",
+                    path.display(), line, column + 1, name,
+                );
+            }
+            source.replace_range(start .. end, code.trim_end());
+        }
+        fs::write(path, source)
+            .map_err(|err| format!("failed to write `{}`: {}", path.display(), err))?;
+    } else {
+        for Replacement { name, start, code, .. } in &replacements {
+            println!(
+                "// `{}`, at {}:{}:{}\n{}",
+                name, path.display(), start.line, start.column + 1, code,
+            );
+        }
+    }
+    if replacements.is_empty() {
+        eprintln!("warning: no `#[with]` item was expanded");
+    } else if in_place {
+        eprintln!("\
+            warning: the rewritten items refer to the `::with_locals::__::*` \
+            internals, which are exempt from semver guarantees and may change \
+            in any release.\n\
+            \x20        Pin the exact version of `with_locals` the file was \
+            migrated with (`with_locals = \"=x.y.z\"`).\
+        ");
+    }
+    Ok(())
+}
+
+/// Whether `code` (seemingly) has non-doc comments, which its expansion lacks.
+fn has_comments (code: &'_ str)
+  -> bool
+{
+    code.lines().any(|line| {
+        let line = line.trim_start();
+        let is_doc =
+            (line.starts_with("///") && line.starts_with("////").not())
+            || line.starts_with("//!")
+            || line.starts_with("/**")
+            || line.starts_with("/*!")
+        ;
+        is_doc.not() && (line.contains("//") || line.contains("/*"))
+    })
+}
+
+/// An outermost `#[with]` item, to be replaced with its (formatted) expansion.
+struct Replacement {
+    name: String,
+    start: LineColumn,
+    end: LineColumn,
+    code: String,
+}
+
+/// The kind of code an expansion consists of, for formatting purposes.
+#[derive(Clone, Copy)]
+enum Fragment {
+    Items,
+    /// The items of an `impl` or `trait` block.
+    AssocItems,
+    Expr,
+}
+
+/// The enclosing (non-annotated) `impl` or `trait` of a `#[with]` method.
+#[derive(Clone)]
+enum Scope {
+    Impl(ItemImpl),
+    Trait(ItemTrait),
+}
+
+struct Expander<'__> {
+    filter: Option<&'__ str>,
+
+    /// `None` when visiting the output of an expansion, in which case every
+    /// remaining `#[with]` item is expanded, in place.
+    replacements: Option<&'__ mut Vec<Replacement>>,
+
+    scope: Option<Scope>,
+
+    /// Whether within a `#[with]` item left unexpanded by `--filter`, where
+    /// the `with!(...)` markers are legitimate.
+    within_with: bool,
+
+    warnings: Vec<(LineColumn, &'static str)>,
+
+    encountered_error: Option<Error>,
+}
+
+impl Expander<'_> {
+    /// Whether the item named `name` is to be expanded: `--filter` only
+    /// applies to the outermost `#[with]` items.
+    fn filter (
+        self: &'_ Self,
+        name: &'_ str,
+    ) -> bool
+    {
+        self.replacements.is_none()
+        || self.filter.iter().all(|filter| name.contains(filter))
+    }
+
+    /// Registers `expansion` as the replacement of `original`, when at the
+    /// top-level.
+    fn register (
+        self: &'_ mut Self,
+        name: String,
+        original: &'_ dyn ToTokens,
+        expansion: TokenStream2,
+        fragment: Fragment,
+    )
+    {
+        if let Some(ref mut replacements) = self.replacements {
+            let (start, end) = span_range(original);
+            replacements.push(Replacement {
+                name,
+                start,
+                end,
+                code: format(&expansion, fragment),
+            });
+        }
+    }
+
+    fn fail (self: &'_ mut Self, err: Error)
+    {
+        match self.encountered_error {
+            | Some(ref mut prev) => prev.combine(err),
+            | None => self.encountered_error = Some(err),
+        }
+    }
+
+    fn expand_or_visit (
+        self: &'_ mut Self,
+        mut item: Item,
+    ) -> Vec<Item>
+    {
+        let name = item_name(&item);
+        let mut stripped = item.clone();
+        match item_attrs(&mut stripped).and_then(take_with_attr) {
+            | Some(attr) if self.filter(&name) => {
+                match expand_item(attr, stripped) {
+                    | Ok(expansion) => {
+                        self.register(
+                            name,
+                            &item,
+                            quote!( #(#expansion)* ),
+                            Fragment::Items,
+                        );
+                        expansion
+                    },
+                    | Err(err) => {
+                        self.fail(err);
+                        vec![item]
+                    },
+                }
+            },
+            | attr => {
+                let prev = self.within_with;
+                self.within_with |= attr.is_some();
+                self.visit_item_mut(&mut item);
+                self.within_with = prev;
+                vec![item]
+            },
+        }
+    }
+
+    /// Expands a `with_block! { ... }` invocation (and whatever it contains),
+    /// if not filtered out.
+    fn expand_with_block (
+        self: &'_ mut Self,
+        mac: &'_ Macro,
+    ) -> Option<Expr>
+    {
+        if self.filter("with_block").not() {
+            return None;
+        }
+        let expansion = (|| Ok({
+            let expansion = ::with_locals_core::expand_block(mac.tokens.clone())?;
+            let mut expr: Expr = parse2(expansion)?;
+            let mut nested = Expander::nested();
+            nested.visit_expr_mut(&mut expr);
+            if let Some(err) = nested.encountered_error {
+                return Err(err);
+            }
+            expr
+        }))();
+        match expansion {
+            | Ok(expr) => {
+                self.register(
+                    "with_block".into(),
+                    mac,
+                    expr.to_token_stream(),
+                    Fragment::Expr,
+                );
+                Some(expr)
+            },
+            | Err(err) => {
+                self.fail(err);
+                None
+            },
+        }
+    }
+
+    fn expand_items (
+        self: &'_ mut Self,
+        items: &'_ mut Vec<Item>,
+    )
+    {
+        *items =
+            mem::take(items)
+                .into_iter()
+                .flat_map(|item| self.expand_or_visit(item))
+                .collect()
+        ;
+    }
+
+    /// A `#[with]` method is expanded within a copy of its enclosing `impl` or
+    /// `trait` (stripped from its other items), which yields the same code as
+    /// the attribute applied to the method itself.
+    fn expand_method<M : Method> (
+        self: &'_ mut Self,
+        method: M,
+    ) -> Vec<M::Item>
+    {
+        let name = method.name();
+        let has_with = method.attrs().iter().any(is_with_attr);
+        if has_with.not() || self.filter(&name).not() {
+            let mut it = M::wrap(method);
+            let prev = self.within_with;
+            self.within_with |= has_with;
+            M::visit(self, &mut it);
+            self.within_with = prev;
+            return vec![it];
+        }
+        let expansion = (|| Ok({
+            let mut scope = self.scope.clone().unwrap();
+            let tokens = match scope {
+                | Scope::Impl(ref mut impl_) => {
+                    impl_.items.push(parse2(method.to_token_stream())?);
+                    impl_.into_token_stream()
+                },
+                | Scope::Trait(ref mut trait_) => {
+                    trait_.items.push(parse2(method.to_token_stream())?);
+                    trait_.into_token_stream()
+                },
+            };
+            let expansion = ::with_locals_core::expand(quote!(), tokens)?;
+            let mut nested = Expander::nested();
+            let items = match scope {
+                | Scope::Impl(_) => {
+                    let mut impl_: ItemImpl = parse2(expansion)?;
+                    nested.visit_item_impl_mut(&mut impl_);
+                    M::items(Scope::Impl(impl_))
+                },
+                | Scope::Trait(_) => {
+                    let mut trait_: ItemTrait = parse2(expansion)?;
+                    nested.visit_item_trait_mut(&mut trait_);
+                    M::items(Scope::Trait(trait_))
+                },
+            };
+            if let Some(err) = nested.encountered_error {
+                return Err(err);
+            }
+            items
+        }))();
+        match expansion {
+            | Ok(expansion) => {
+                self.register(
+                    name,
+                    &method,
+                    quote!( #(#expansion)* ),
+                    Fragment::AssocItems,
+                );
+                expansion
+            },
+            | Err(err) => {
+                self.fail(err);
+                vec![M::wrap(method)]
+            },
+        }
+    }
+
+    fn nested ()
+      -> Expander<'static>
+    {
+        Expander {
+            filter: None,
+            replacements: None,
+            scope: None,
+            within_with: false,
+            warnings: vec![],
+            encountered_error: None,
+        }
+    }
+}
+
+impl VisitMut for Expander<'_> {
+    fn visit_file_mut (
+        self: &'_ mut Self,
+        file: &'_ mut File,
+    )
+    {
+        self.expand_items(&mut file.items);
+    }
+
+    fn visit_item_mod_mut (
+        self: &'_ mut Self,
+        mod_: &'_ mut ItemMod,
+    )
+    {
+        if let Some((_, ref mut items)) = mod_.content {
+            self.expand_items(items);
+        }
+    }
+
+    fn visit_block_mut (
+        self: &'_ mut Self,
+        block: &'_ mut Block,
+    )
+    {
+        block.stmts =
+            mem::take(&mut block.stmts)
+                .into_iter()
+                .flat_map(|stmt| match stmt {
+                    | Stmt::Item(Item::Macro(ItemMacro { ref mac, ident: None, .. }))
+                        if is_with_block_macro(mac)
+                    => {
+                        match self.expand_with_block(mac) {
+                            | Some(expr) => vec![Stmt::Expr(expr)],
+                            | None => vec![stmt],
+                        }
+                    },
+                    | Stmt::Item(item) => {
+                        self.expand_or_visit(item)
+                            .into_iter()
+                            .map(Stmt::Item)
+                            .collect()
+                    },
+                    | mut stmt => {
+                        visit_mut::visit_stmt_mut(self, &mut stmt);
+                        vec![stmt]
+                    },
+                })
+                .collect()
+        ;
+    }
+
+    fn visit_expr_mut (
+        self: &'_ mut Self,
+        expr: &'_ mut Expr,
+    )
+    {
+        if let Expr::Macro(ExprMacro { ref mac, .. }) = *expr {
+            if is_with_block_macro(mac) {
+                if let Some(expansion) = self.expand_with_block(mac) {
+                    *expr = expansion;
+                }
+                return;
+            }
+            if is_with_path(&mac.path, "with") && self.within_with.not() {
+                self.warnings.push((
+                    span_range(mac).0,
+                    "`with!(...)` outside of a `#[with]` item is left as is",
+                ));
+            }
+        }
+        visit_mut::visit_expr_mut(self, expr);
+    }
+
+    fn visit_item_impl_mut (
+        self: &'_ mut Self,
+        impl_: &'_ mut ItemImpl,
+    )
+    {
+        let mut header = impl_.clone();
+        header.attrs.clear();
+        header.items.clear();
+        let prev_scope = self.scope.replace(Scope::Impl(header));
+        impl_.items =
+            mem::take(&mut impl_.items)
+                .into_iter()
+                .flat_map(|it| match it {
+                    | ImplItem::Method(method) => self.expand_method(method),
+                    | mut it => {
+                        visit_mut::visit_impl_item_mut(self, &mut it);
+                        vec![it]
+                    },
+                })
+                .collect()
+        ;
+        self.scope = prev_scope;
+    }
+
+    fn visit_item_trait_mut (
+        self: &'_ mut Self,
+        trait_: &'_ mut ItemTrait,
+    )
+    {
+        let mut header = trait_.clone();
+        header.attrs.clear();
+        header.items.clear();
+        let prev_scope = self.scope.replace(Scope::Trait(header));
+        trait_.items =
+            mem::take(&mut trait_.items)
+                .into_iter()
+                .flat_map(|it| match it {
+                    | TraitItem::Method(method) => self.expand_method(method),
+                    | mut it => {
+                        visit_mut::visit_trait_item_mut(self, &mut it);
+                        vec![it]
+                    },
+                })
+                .collect()
+        ;
+        self.scope = prev_scope;
+    }
+}
+
+/// Abstracts over `ImplItemMethod` and `TraitItemMethod`.
+trait Method : Clone + ToTokens {
+    type Item : ToTokens;
+
+    fn attrs (self: &'_ Self)
+      -> &'_ [Attribute]
+    ;
+
+    fn name (self: &'_ Self)
+      -> String
+    ;
+
+    fn wrap (self: Self)
+      -> Self::Item
+    ;
+
+    fn visit (expander: &'_ mut Expander<'_>, it: &'_ mut Self::Item)
+    ;
+
+    fn items (scope: Scope)
+      -> Vec<Self::Item>
+    ;
+}
+
+impl Method for ImplItemMethod {
+    type Item = ImplItem;
+
+    fn attrs (self: &'_ Self)
+      -> &'_ [Attribute]
+    {
+        &self.attrs
+    }
+
+    fn name (self: &'_ Self)
+      -> String
+    {
+        self.sig.ident.to_string()
+    }
+
+    fn wrap (self: Self)
+      -> ImplItem
+    {
+        ImplItem::Method(self)
+    }
+
+    fn visit (expander: &'_ mut Expander<'_>, it: &'_ mut ImplItem)
+    {
+        visit_mut::visit_impl_item_mut(expander, it);
+    }
+
+    fn items (scope: Scope)
+      -> Vec<ImplItem>
+    {
+        match scope {
+            | Scope::Impl(impl_) => impl_.items,
+            | Scope::Trait(_) => unreachable!(),
+        }
+    }
+}
+
+impl Method for TraitItemMethod {
+    type Item = TraitItem;
+
+    fn attrs (self: &'_ Self)
+      -> &'_ [Attribute]
+    {
+        &self.attrs
+    }
+
+    fn name (self: &'_ Self)
+      -> String
+    {
+        self.sig.ident.to_string()
+    }
+
+    fn wrap (self: Self)
+      -> TraitItem
+    {
+        TraitItem::Method(self)
+    }
+
+    fn visit (expander: &'_ mut Expander<'_>, it: &'_ mut TraitItem)
+    {
+        visit_mut::visit_trait_item_mut(expander, it);
+    }
+
+    fn items (scope: Scope)
+      -> Vec<TraitItem>
+    {
+        match scope {
+            | Scope::Trait(trait_) => trait_.items,
+            | Scope::Impl(_) => unreachable!(),
+        }
+    }
+}
+
+/// Expands a single `#[with(...)]`-annotated item (whose attribute has already
+/// been removed), along with the `#[with]` items remaining in its expansion.
+fn expand_item (attr: Attribute, item: Item)
+  -> Result<Vec<Item>>
+{
+    let expansion = ::with_locals_core::expand(
+        attr_args(&attr)?,
+        item.into_token_stream(),
+    )?;
+    let mut file: File = parse2(expansion)?;
+    let mut nested = Expander::nested();
+    nested.visit_file_mut(&mut file);
+    if let Some(err) = nested.encountered_error {
+        return Err(err);
+    }
+    Ok(file.items)
+}
+
+/// `#[with]` -> ``, `#[with(args)]` -> `args`.
+fn attr_args (attr: &'_ Attribute)
+  -> Result<TokenStream2>
+{
+    if attr.tokens.is_empty() {
+        Ok(TokenStream2::new())
+    } else {
+        attr.parse_args()
+    }
+}
+
+/// Removes the `#[with]` attribute, if any, and returns it.
+fn take_with_attr (attrs: &'_ mut Vec<Attribute>)
+  -> Option<Attribute>
+{
+    let idx = attrs.iter().position(is_with_attr)?;
+    Some(attrs.remove(idx))
+}
+
+fn is_with_attr (attr: &'_ Attribute)
+  -> bool
+{
+    is_with_path(&attr.path, "with")
+}
+
+fn is_with_block_macro (mac: &'_ Macro)
+  -> bool
+{
+    is_with_path(&mac.path, "with_block")
+}
+
+/// `name`, `with_locals::name`, or `crate::name` (_i.e._, a re-export).
+fn is_with_path (path: &'_ Path, name: &'_ str)
+  -> bool
+{
+    let mut segments = path.segments.iter().map(|it| it.ident.to_string());
+    match (segments.next(), segments.next(), segments.next()) {
+        | (Some(it), None, None) => path.leading_colon.is_none() && it == name,
+        | (Some(krate), Some(it), None) => {
+            (krate == "with_locals" || krate == "crate") && it == name
+        },
+        | _ => false,
+    }
+}
+
+fn item_attrs (item: &'_ mut Item)
+  -> Option<&'_ mut Vec<Attribute>>
+{
+    Some(match *item {
+        | Item::Fn(ItemFn { ref mut attrs, .. })
+        | Item::Impl(ItemImpl { ref mut attrs, .. })
+        | Item::Mod(ItemMod { ref mut attrs, .. })
+        | Item::Trait(ItemTrait { ref mut attrs, .. })
+        | Item::Struct(ItemStruct { ref mut attrs, .. })
+        | Item::Enum(ItemEnum { ref mut attrs, .. })
+        | Item::Union(ItemUnion { ref mut attrs, .. })
+        | Item::Const(ItemConst { ref mut attrs, .. })
+        | Item::Static(ItemStatic { ref mut attrs, .. })
+        | Item::Type(ItemType { ref mut attrs, .. })
+        => attrs,
+        | _ => return None,
+    })
+}
+
+/// The bare name of an item, as matched by `--filter`.
+fn item_name (item: &'_ Item)
+  -> String
+{
+    match *item {
+        | Item::Fn(ref it) => it.sig.ident.to_string(),
+        | Item::Impl(ref it) => it.self_ty.to_token_stream().to_string(),
+        | Item::Mod(ref it) => it.ident.to_string(),
+        | Item::Trait(ref it) => it.ident.to_string(),
+        | _ => String::new(),
+    }
+}
+
+/// The source range covered by the tokens of `tokens`.
+fn span_range (tokens: &'_ dyn ToTokens)
+  -> (LineColumn, LineColumn)
+{
+    let tokens = tokens.to_token_stream();
+    let mut iter = tokens.into_iter();
+    let first = iter.next().expect("empty item");
+    let last = iter.last().unwrap_or_else(|| first.clone());
+    let end = match last {
+        | TokenTree::Group(ref group) => group.span_close().end(),
+        | ref tt => tt.span().end(),
+    };
+    (first.span().start(), end)
+}
+
+/// The byte offset of a (1-based) line and (0-based, in `char`s) column.
+fn byte_offset (source: &'_ str, LineColumn { line, column }: LineColumn)
+  -> usize
+{
+    let line_start =
+        source
+            .split_inclusive('\n')
+            .take(line - 1)
+            .map(str::len)
+            .sum::<usize>()
+    ;
+    line_start
+    + source[line_start ..]
+        .char_indices()
+        .nth(column)
+        .map_or(source.len() - line_start, |(offset, _)| offset)
+}
+
+/// Formats the expansion with `rustfmt`, if available.
+///
+/// Associated items and expressions are formatted within a dummy `impl` block
+/// and `const` respectively, since they do not constitute a valid source file
+/// on their own.
+fn format (code: &'_ TokenStream2, fragment: Fragment)
+  -> String
+{
+    use ::with_locals_core::rustfmt;
+
+    match fragment {
+        | Fragment::Items => if let Some(formatted) = rustfmt(&code.to_string()) {
+            return formatted;
+        },
+        | Fragment::AssocItems => {
+            let wrapped = format!("impl __ {{\n{}\n}}", code);
+            if let Some(formatted) = rustfmt(&wrapped) {
+                let lines: Vec<&str> = formatted.trim_end().lines().collect();
+                if let [_impl, inner @ .., _close] = &lines[..] {
+                    return inner.iter().map(|line| {
+                        line.strip_prefix("    ").unwrap_or(line).to_owned() + "\n"
+                    }).collect();
+                }
+            }
+        },
+        | Fragment::Expr => {
+            const PREFIX: &str = "const _: () = ";
+            let wrapped = format!("{}{};", PREFIX, code);
+            if let Some(formatted) = rustfmt(&wrapped) {
+                let formatted = formatted.trim_end();
+                if let Some(expr) =
+                    formatted.strip_prefix(PREFIX).and_then(|it| it.strip_suffix(';'))
+                {
+                    return expr.to_owned() + "\n";
+                }
+            }
+        },
+    }
+    // Fallback to raw output.
+    code.to_string() + "\n"
+}
@@ -0,0 +1,49 @@
+//! Snapshot tests: the fixtures of `tests/fixtures` are expanded in place (in
+//! a temporary copy), and the result compared, token-wise (so as not to depend
+//! on `rustfmt` being available), with the `.expanded.rs` snapshot.
+
+use ::std::{
+    env,
+    fs,
+    path::Path,
+    process::Command,
+};
+
+use ::core::ops::Not as _;
+
+fn tokens (path: &'_ Path)
+  -> String
+{
+    let source = fs::read_to_string(path).unwrap();
+    let file =
+        ::syn::parse_file(&source)
+            .unwrap_or_else(|err| panic!("`{}` failed to parse: {}", path.display(), err))
+    ;
+    ::quote::ToTokens::into_token_stream(file).to_string()
+}
+
+#[test]
+fn expand_in_place ()
+{
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let tmp = env::temp_dir().join(format!(
+        "with_locals-expand-tests-{}.rs", ::std::process::id(),
+    ));
+    fs::copy(fixtures.join("input.rs"), &tmp).unwrap();
+    let output =
+        Command::new(env!("CARGO_BIN_EXE_with-locals-expand"))
+            .arg("--in-place")
+            .arg("--manifest-path").arg(fixtures.join("metadata.toml"))
+            .arg(&tmp)
+            .output()
+            .unwrap()
+    ;
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("the comments within `print_hex` have been dropped"));
+    assert!(stderr.contains("the comments within `hex` have").not());
+    assert!(stderr.contains("`::with_locals::__::*` internals"));
+    let (actual, expected) = (tokens(&tmp), tokens(&fixtures.join("input.expanded.rs")));
+    fs::remove_file(&tmp).unwrap();
+    assert_eq!(actual, expected);
+}
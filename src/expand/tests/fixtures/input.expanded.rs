@@ -0,0 +1,97 @@
+use ::with_locals::{with, with_block};
+
+// Formats `n` in hexadecimal.
+pub fn scoped_hex<
+    __Continuation_Return__,
+    __Continuation__: ::with_locals::__::FnOnce(&'_ dyn ::core::fmt::Display) -> __Continuation_Return__,
+>(
+    n: u32,
+    __continuation__: __Continuation__,
+) -> __Continuation_Return__ {
+    #[doc = r" Some user-provided code patterns, once transformed, may scare"]
+    #[doc = r" Rust into thinking we are calling an `FnOnce()` multiple times."]
+    #[doc = r" Since that _shouldn't_ be the case, we defer to a runtime check,"]
+    #[doc = r" hoping that, in practice, it will end up being optimized away."]
+    extern "C" {}
+    let mut __continuation__ = {
+        let mut __continuation__ = ::with_locals::__::Some_(__continuation__);
+        move |__ret__: &'_ dyn ::core::fmt::Display| {
+            __continuation__.take().expect(
+                "\
+                            Fatal `with_locals` error: \
+                            attempted to call an `FnOnce()` multiple times.\
+                        ",
+            )(__ret__)
+        }
+    };
+    return __continuation__(&format_args!("{:#x}", n));
+}
+#[allow(dead_code, nonstandard_style)]
+#[doc(hidden)]
+#[deprecated(
+    note = "\n`hex` is a with-function: the value it lends can only be used within a continuation.\n  - either use `#[with] let x = hex(...);` (inside a `#[with]`-annotated function),\n  - or call `scoped_hex(..., |x| { ... })` directly."
+)]
+pub fn hex<T>(_: ::with_locals::__::WithFunction) -> T {
+    ::core::panic!()
+}
+
+fn print_hex() {
+    match scoped_hex(66, |s| {
+        #[allow(clippy::diverging_sub_expression)]
+        let __with_locals_eval__ = {
+            println!("{}", s);
+        };
+        #[allow(unreachable_code)]
+        ::with_locals::__::ControlFlow::<
+            _,
+            ::with_locals::__::Unreachable,
+            ::with_locals::__::Unreachable,
+            ::with_locals::__::Unreachable,
+        >::Eval(__with_locals_eval__)
+    }) {
+        ::with_locals::__::ControlFlow::Eval(it) => it,
+        ::with_locals::__::ControlFlow::EarlyReturn(it) => {
+            let unreachable = it;
+            match unreachable {}
+        }
+        ::with_locals::__::ControlFlow::Break(it) => {
+            let unreachable = it;
+            match unreachable {}
+        }
+        ::with_locals::__::ControlFlow::Continue(it) => {
+            let unreachable = it;
+            match unreachable {}
+        }
+    }
+}
+
+fn hex_string () -> String
+{
+    {
+        match scoped_hex(27, |s| {
+            #[allow(clippy::diverging_sub_expression)]
+            let __with_locals_eval__ = { s.to_string() };
+            #[allow(unreachable_code)]
+            ::with_locals::__::ControlFlow::<
+                _,
+                ::with_locals::__::Unreachable,
+                ::with_locals::__::Unreachable,
+                ::with_locals::__::Unreachable,
+            >::Eval(__with_locals_eval__)
+        }) {
+            ::with_locals::__::ControlFlow::Eval(it) => it,
+            ::with_locals::__::ControlFlow::EarlyReturn(it) => {
+                let unreachable = it;
+                match unreachable {}
+            }
+            ::with_locals::__::ControlFlow::Break(it) => {
+                let unreachable = it;
+                match unreachable {}
+            }
+            ::with_locals::__::ControlFlow::Continue(it) => {
+                let unreachable = it;
+                match unreachable {}
+            }
+        }
+    }
+}
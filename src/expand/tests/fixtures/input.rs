@@ -0,0 +1,27 @@
+use ::with_locals::{with, with_block};
+
+// Formats `n` in hexadecimal.
+#[with]
+pub
+fn hex (n: u32) -> &'local dyn ::core::fmt::Display
+{
+    &format_args!("{:#x}", n)
+}
+
+#[with_locals::with]
+fn print_hex ()
+{
+    #[with]
+    let s = hex(66);
+    // Dropped by the expansion.
+    println!("{}", s);
+}
+
+fn hex_string () -> String
+{
+    with_block! {
+        #[with]
+        let s = hex(27);
+        s.to_string()
+    }
+}
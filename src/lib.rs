@@ -1,4 +1,9 @@
 #![forbid(unsafe_code)]
+// The `self: &'_ Self` spelling is deliberate.
+#![allow(clippy::needless_arbitrary_self_type)]
+#![cfg_attr(feature = "nightly",
+    feature(try_trait_v2),
+)]
 
 #![cfg_attr(feature = "nightly",
     cfg_attr(all(), doc = include_str!("../README.md")),
@@ -7,122 +12,180 @@
 /// To avoid a bug when cross compiling
 extern crate proc_macros;
 
-pub use ::proc_macros::with;
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+pub use ::proc_macros::{with, with_block};
+
+pub use try_trait::{FromResidual, Try};
+mod try_trait;
+
+pub
+mod cps;
 
 /// For advanced users that manually write the `with` closure of `dyn_safe`
 /// function.
 pub
 mod dyn_safe {
-    /// Used to manually call `#[with(dyn_safe = true)]` functions.
+    /// Used to manually call `#[with(dyn_safe = true)]` (or
+    /// `#[with(dyn_safe = "boxed")]`) functions.
     ///
     /// They need a fixed / non-generic return type, but using `()` would be
     /// error-prone when manually implementing such `with` functions. Using
     /// this is thus more type-safe.
     pub
     struct ContinuationReturn;
-}
-
-#[doc(hidden)] /** Not part of the public API **/ pub
-mod __ {
-    pub
-    enum ControlFlow<Eval, Return, Break, Continue> {
-        /// Classic block evaluation.
-        Eval(Eval),
-
-        /// Must `return` the value early.
-        EarlyReturn(Return),
-
-        /// Must `break` with the value.
-        Break(Break),
-
-        /// Must `continue`.
-        Continue(Continue),
-    }
-
-    /// Custom *void type*
-    pub
-    enum Unreachable {}
-
-    pub
-    use ::core::{
-        convert::Into,
-        ops::{
-            FnMut, FnOnce,
-        },
-        option::Option::{Some as Some_, None as None_},
-        result::Result::{Ok as Ok_, Err as Err_},
-    };
 
+    /// Holds the continuation of a manual call to a `#[with(dyn_safe = true)]`
+    /// function, as well as the value it eventually returns.
+    ///
+    /// `dyn`-safe functions take their continuation as a
+    /// `&mut dyn FnMut(...) -> ContinuationReturn`, so the actual `FnOnce()`
+    /// continuation and its return value need to live somewhere else: here.
+    ///
+    /// This is the very same helper that `#[with(dyn_safe)] let` statements
+    /// expand to, so most of the time you will want to use [`call`] instead.
     pub
-    trait Try {
-        type Ok;
-        type Error;
-
-        fn into_result (self: Self)
-          -> Result<Self::Ok, Self::Error>
-        ;
-
-        fn from_ok (ok: Self::Ok)
-          -> Self
-        ;
-
-        fn from_err (err: Self::Error)
-          -> Self
-        ;
+    struct Slot<R, Continuation> {
+        continuation: Option<Continuation>,
+        ret: Option<R>,
     }
 
-    impl<Ok, Err> Try for Result<Ok, Err> {
-        type Ok = Ok;
-        type Error = Err;
-
+    impl<R, Continuation> Slot<R, Continuation> {
+        /// Stores the given continuation, so that it can later be [`fed`](
+        /// Slot::feed) the value lent by the `dyn`-safe function.
         #[inline]
-        fn into_result (self: Result<Ok, Err>)
-          -> Result<Ok, Err>
+        pub
+        fn new (continuation: Continuation)
+          -> Slot<R, Continuation>
         {
-            self
+            Slot {
+                continuation: Some(continuation),
+                ret: None,
+            }
         }
 
+        /// Calls the stored continuation, and stores its return value.
+        ///
+        /// This is the body of the `&mut |it| slot.feed(it)` closure that ought
+        /// to be given to the `dyn`-safe function.
+        ///
+        /// # Panics
+        ///
+        /// If called more than once.
         #[inline]
-        fn from_ok (ok: Ok)
-          -> Result<Ok, Err>
+        pub
+        fn feed<Arg> (self: &'_ mut Slot<R, Continuation>, arg: Arg)
+          -> ContinuationReturn
+        where
+            Continuation : FnOnce(Arg) -> R,
         {
-            Ok(ok)
+            let continuation =
+                self.continuation
+                    .take()
+                    .expect("\
+                        Fatal `with_locals` error: \
+                        attempted to call an `FnOnce()` multiple times.\
+                    ")
+            ;
+            self.ret = Some(continuation(arg));
+            ContinuationReturn
         }
 
+        /// Extracts the value returned by the continuation.
+        ///
+        /// # Panics
+        ///
+        /// If the continuation was never called.
         #[inline]
-        fn from_err (err: Err)
-          -> Result<Ok, Err>
+        pub
+        fn into_inner (self: Slot<R, Continuation>)
+          -> R
         {
-            Err(err)
+            self.ret.expect("\
+                Fatal `with_locals` error: \
+                failed to call the continuation.\
+            ")
         }
     }
 
-    mod hidden { pub struct NoneError; }
-    use hidden::NoneError;
+    /// Manually call a `#[with(dyn_safe = true)]` function.
+    ///
+    /// The `continuation` comes first, and the second closure is then expected
+    /// to perform the call to the `dyn`-safe function, using
+    /// `&mut |it| slot.feed(it)` as its continuation parameter.
+    ///
+    /// Note that, since the `continuation` is not given directly to the
+    /// `dyn`-safe function, its parameter may need a type annotation.
+    ///
+    /// ```rust
+    /// use ::with_locals::{dyn_safe, with};
+    ///
+    /// #[with('local, dyn_safe = true)]
+    /// trait Hex {
+    ///     fn hex (&self) -> &'local str;
+    /// }
+    ///
+    /// impl Hex for u32 {
+    ///     #[with('local, dyn_safe = true)]
+    ///     fn hex (&self) -> &'local str
+    ///     {
+    ///         &format!("{:#x}", self)
+    ///     }
+    /// }
+    ///
+    /// let obj: &dyn Hex = &66;
+    /// let len = dyn_safe::call(
+    ///     |s: &str| s.len(),
+    ///     |slot| obj.with_hex(&mut |it| slot.feed(it)),
+    /// );
+    /// assert_eq!(len, "0x42".len());
+    /// ```
+    pub
+    fn call<R, Continuation> (
+        continuation: Continuation,
+        with: impl FnOnce(&'_ mut Slot<R, Continuation>) -> ContinuationReturn,
+    ) -> R
+    {
+        let mut slot = Slot::new(continuation);
+        let ContinuationReturn = with(&mut slot);
+        slot.into_inner()
+    }
+}
+
+#[doc(hidden)] /** Not part of the public API **/ pub
+mod __ {
+    pub
+    use crate::cps::ControlFlow;
 
-    impl<T> Try for Option<T> {
-        type Ok = T;
-        type Error = NoneError;
+    /// Custom *void type*
+    pub
+    enum Unreachable {}
 
-        #[inline]
-        fn into_result (self: Option<T>)
-          -> Result<T, NoneError>
-        {
-            self.ok_or(NoneError)
-        }
+    /// Parameter type of the hidden `fn`s shadowing the original name of
+    /// with-functions, so that calling them without `#[with]` fails with a
+    /// helpful message.
+    pub
+    struct WithFunction;
 
-        #[inline]
-        fn from_ok (value: T)
-          -> Option<T>
-        {
-            Some(value)
-        }
+    pub
+    use crate::dyn_safe::{ContinuationReturn, Slot};
 
-        #[inline]
-        fn from_err (NoneError: NoneError)
-          -> Option<T>
-        {
-            None
-        }
-    }
+    #[cfg(feature = "alloc")]
+    pub
+    use ::alloc::boxed::Box;
+
+    pub
+    use ::core::{
+        ops::{
+            FnMut, FnOnce,
+        },
+        option::Option::{Some as Some_, None as None_},
+    };
+
+    pub
+    use crate::{FromResidual, Try};
+
+    pub
+    use ::core::ops::ControlFlow::{Break as Break_, Continue as Continue_};
 }
@@ -0,0 +1,332 @@
+//! The `?` operator within with-functions and continuation bodies.
+//!
+//! Since such a `?` may need to `return` from within a closure (the
+//! continuation), `#[with]` rewrites `<expr>?` as:
+//!
+//! ```rust,ignore
+//! match Try::branch(<expr>) {
+//!     ControlFlow::Continue(output) => output,
+//!     ControlFlow::Break(residual) => {
+//!         return continuation(FromResidual::from_residual(residual));
+//!     },
+//! }
+//! ```
+//!
+//! which works with any type implementing these traits: a stable mirror of
+//! the unstable `::core::ops::{Try, FromResidual}` ones. With the `nightly`
+//! Cargo feature, these are implemented for every `?`-able type, by delegating
+//! to the `::core::ops` ones.
+
+use ::core::ops::ControlFlow;
+
+#[cfg(not(feature = "nightly"))]
+use ::core::{
+    convert::Infallible,
+    task::Poll,
+};
+
+/// The types `?` can be applied to (within `#[with]`-annotated code).
+///
+/// Implemented for `Result`, `Option`, [`ControlFlow`], and
+/// `Poll<Result<..>>` / `Poll<Option<Result<..>>>`, and implementable for
+/// custom result-like types:
+///
+/// ```rust
+/// use ::core::{convert::Infallible, ops::ControlFlow};
+/// use ::with_locals::{with, FromResidual, Try};
+///
+/// enum Outcome<T, E> {
+///     Success(T),
+///     Failure(E),
+/// }
+///
+/// impl<T, E> Try for Outcome<T, E> {
+///     type Output = T;
+///     type Residual = Outcome<Infallible, E>;
+///
+///     fn from_output (output: T)
+///       -> Self
+///     {
+///         Outcome::Success(output)
+///     }
+///
+///     fn branch (self)
+///       -> ControlFlow<Self::Residual, T>
+///     {
+///         match self {
+///             | Outcome::Success(it) => ControlFlow::Continue(it),
+///             | Outcome::Failure(err) => ControlFlow::Break(Outcome::Failure(err)),
+///         }
+///     }
+/// }
+///
+/// impl<T, E> FromResidual<Outcome<Infallible, E>> for Outcome<T, E> {
+///     fn from_residual (residual: Outcome<Infallible, E>)
+///       -> Self
+///     {
+///         match residual {
+///             | Outcome::Success(unreachable) => match unreachable {},
+///             | Outcome::Failure(err) => Outcome::Failure(err),
+///         }
+///     }
+/// }
+///
+/// #[with('local)]
+/// fn parse (s: &'_ str) -> Outcome<&'local str, ()>
+/// {
+///     let n = if let Ok(n) = s.parse::<u8>() {
+///         Outcome::Success(n)
+///     } else {
+///         Outcome::Failure(())
+///     };
+///     Outcome::Success(&n?.to_string())
+/// }
+///
+/// #[with('local)]
+/// fn len (s: &'_ str) -> Outcome<usize, ()>
+/// {
+///     let s: &'local str = parse(s)?;
+///     Outcome::Success(s.len())
+/// }
+/// # assert!(matches!(len("42"), Outcome::Success(2)));
+/// # assert!(matches!(len("-1"), Outcome::Failure(())));
+/// ```
+pub
+trait Try : FromResidual<<Self as Try>::Residual> {
+    /// The type of the value produced by `?` when _not_ short-circuiting.
+    type Output;
+
+    /// The type of the value passed to [`FromResidual::from_residual`] as
+    /// part of `?` when short-circuiting.
+    type Residual;
+
+    /// Constructs the type from its `Output` type.
+    fn from_output (output: Self::Output)
+      -> Self
+    ;
+
+    /// Used in `?` to decide whether the operator should produce a value
+    /// (because this returned [`ControlFlow::Continue`]) or propagate a value
+    /// back to the caller (because this returned [`ControlFlow::Break`]).
+    fn branch (self: Self)
+      -> ControlFlow<Self::Residual, Self::Output>
+    ;
+}
+
+/// The types a `?` can early-return: those which can be built out of the
+/// [`Try::Residual`] of the `?`-ed expression.
+pub
+trait FromResidual<Residual> {
+    /// Constructs the type from a compatible `Residual` type.
+    fn from_residual (residual: Residual)
+      -> Self
+    ;
+}
+
+#[cfg(not(feature = "nightly"))]
+const _: () = {
+    impl<T, E> Try for Result<T, E> {
+        type Output = T;
+        type Residual = Result<Infallible, E>;
+
+        #[inline]
+        fn from_output (output: T)
+          -> Result<T, E>
+        {
+            Ok(output)
+        }
+
+        #[inline]
+        fn branch (self: Result<T, E>)
+          -> ControlFlow<Result<Infallible, E>, T>
+        {
+            match self {
+                | Ok(it) => ControlFlow::Continue(it),
+                | Err(err) => ControlFlow::Break(Err(err)),
+            }
+        }
+    }
+
+    impl<T, E, F : From<E>> FromResidual<Result<Infallible, E>> for Result<T, F> {
+        #[inline]
+        fn from_residual (residual: Result<Infallible, E>)
+          -> Result<T, F>
+        {
+            match residual {
+                | Ok(unreachable) => match unreachable {},
+                | Err(err) => Err(From::from(err)),
+            }
+        }
+    }
+
+    impl<T> Try for Option<T> {
+        type Output = T;
+        type Residual = Option<Infallible>;
+
+        #[inline]
+        fn from_output (output: T)
+          -> Option<T>
+        {
+            Some(output)
+        }
+
+        #[inline]
+        fn branch (self: Option<T>)
+          -> ControlFlow<Option<Infallible>, T>
+        {
+            match self {
+                | Some(it) => ControlFlow::Continue(it),
+                | None => ControlFlow::Break(None),
+            }
+        }
+    }
+
+    impl<T> FromResidual<Option<Infallible>> for Option<T> {
+        #[inline]
+        fn from_residual (_: Option<Infallible>)
+          -> Option<T>
+        {
+            None
+        }
+    }
+
+    impl<B, C> Try for ControlFlow<B, C> {
+        type Output = C;
+        type Residual = ControlFlow<B, Infallible>;
+
+        #[inline]
+        fn from_output (output: C)
+          -> ControlFlow<B, C>
+        {
+            ControlFlow::Continue(output)
+        }
+
+        #[inline]
+        fn branch (self: ControlFlow<B, C>)
+          -> ControlFlow<ControlFlow<B, Infallible>, C>
+        {
+            match self {
+                | ControlFlow::Continue(it) => ControlFlow::Continue(it),
+                | ControlFlow::Break(it) => ControlFlow::Break(ControlFlow::Break(it)),
+            }
+        }
+    }
+
+    impl<B, C> FromResidual<ControlFlow<B, Infallible>> for ControlFlow<B, C> {
+        #[inline]
+        fn from_residual (residual: ControlFlow<B, Infallible>)
+          -> ControlFlow<B, C>
+        {
+            match residual {
+                | ControlFlow::Continue(unreachable) => match unreachable {},
+                | ControlFlow::Break(it) => ControlFlow::Break(it),
+            }
+        }
+    }
+
+    /// `Poll<Result<T, E>>?` evaluates to a `Poll<T>`.
+    impl<T, E> Try for Poll<Result<T, E>> {
+        type Output = Poll<T>;
+        type Residual = Result<Infallible, E>;
+
+        #[inline]
+        fn from_output (output: Poll<T>)
+          -> Poll<Result<T, E>>
+        {
+            output.map(Ok)
+        }
+
+        #[inline]
+        fn branch (self: Poll<Result<T, E>>)
+          -> ControlFlow<Result<Infallible, E>, Poll<T>>
+        {
+            match self {
+                | Poll::Ready(Ok(it)) => ControlFlow::Continue(Poll::Ready(it)),
+                | Poll::Ready(Err(err)) => ControlFlow::Break(Err(err)),
+                | Poll::Pending => ControlFlow::Continue(Poll::Pending),
+            }
+        }
+    }
+
+    impl<T, E, F : From<E>> FromResidual<Result<Infallible, E>> for Poll<Result<T, F>> {
+        #[inline]
+        fn from_residual (residual: Result<Infallible, E>)
+          -> Poll<Result<T, F>>
+        {
+            match residual {
+                | Ok(unreachable) => match unreachable {},
+                | Err(err) => Poll::Ready(Err(From::from(err))),
+            }
+        }
+    }
+
+    /// `Poll<Option<Result<T, E>>>?` evaluates to a `Poll<Option<T>>`.
+    impl<T, E> Try for Poll<Option<Result<T, E>>> {
+        type Output = Poll<Option<T>>;
+        type Residual = Result<Infallible, E>;
+
+        #[inline]
+        fn from_output (output: Poll<Option<T>>)
+          -> Poll<Option<Result<T, E>>>
+        {
+            output.map(|it| it.map(Ok))
+        }
+
+        #[inline]
+        fn branch (self: Poll<Option<Result<T, E>>>)
+          -> ControlFlow<Result<Infallible, E>, Poll<Option<T>>>
+        {
+            match self {
+                | Poll::Ready(Some(Ok(it))) => ControlFlow::Continue(Poll::Ready(Some(it))),
+                | Poll::Ready(Some(Err(err))) => ControlFlow::Break(Err(err)),
+                | Poll::Ready(None) => ControlFlow::Continue(Poll::Ready(None)),
+                | Poll::Pending => ControlFlow::Continue(Poll::Pending),
+            }
+        }
+    }
+
+    impl<T, E, F : From<E>> FromResidual<Result<Infallible, E>>
+        for Poll<Option<Result<T, F>>>
+    {
+        #[inline]
+        fn from_residual (residual: Result<Infallible, E>)
+          -> Poll<Option<Result<T, F>>>
+        {
+            match residual {
+                | Ok(unreachable) => match unreachable {},
+                | Err(err) => Poll::Ready(Some(Err(From::from(err)))),
+            }
+        }
+    }
+};
+
+#[cfg(feature = "nightly")]
+const _: () = {
+    impl<T : ::core::ops::Try> Try for T {
+        type Output = T::Output;
+        type Residual = T::Residual;
+
+        #[inline]
+        fn from_output (output: T::Output)
+          -> T
+        {
+            <T as ::core::ops::Try>::from_output(output)
+        }
+
+        #[inline]
+        fn branch (self: T)
+          -> ControlFlow<T::Residual, T::Output>
+        {
+            <T as ::core::ops::Try>::branch(self)
+        }
+    }
+
+    impl<R, T : ::core::ops::FromResidual<R>> FromResidual<R> for T {
+        #[inline]
+        fn from_residual (residual: R)
+          -> T
+        {
+            <T as ::core::ops::FromResidual<R>>::from_residual(residual)
+        }
+    }
+};
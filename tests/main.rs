@@ -1,4 +1,5 @@
 #![forbid(unsafe_code)]
+#![allow(clippy::needless_arbitrary_self_type)]
 
 #[macro_use]
 extern crate with_locals;
@@ -236,6 +237,719 @@ fn recursive ()
     let _it: &'local () = recursive_f(true);
 }
 
+#[test]
+#[with('local)]
+fn mutually_recursive ()
+{
+    #[with('local, recursive = true)]
+    mod parity {
+        pub
+        fn is_even (n: u32) -> &'local bool
+        {
+            if n == 0 {
+                return &true;
+            }
+            let odd: &'local bool = is_odd(n - 1);
+            let even = *odd;
+            &even
+        }
+
+        pub
+        fn is_odd (n: u32) -> &'local bool
+        {
+            if n == 0 {
+                return &false;
+            }
+            let even: &'local bool = is_even(n - 1);
+            let odd = *even;
+            &odd
+        }
+    }
+    let even: &'local bool = parity::is_even(42);
+    assert!(*even);
+    let odd: &'local bool = parity::is_odd(42);
+    assert!(!*odd);
+}
+
+#[test]
+fn with_block ()
+{
+    #[with('local)]
+    fn hex (n: u32) -> &'local dyn Display
+    {
+        &format_args!("{:#x}", n)
+    }
+
+    let strings = (65 .. 68).map(|n| with_block! {
+        #[with]
+        let s = hex(n);
+        s.to_string()
+    }).collect::<Vec<_>>();
+    assert_eq!(strings, ["0x41", "0x42", "0x43"]);
+
+    // `return` and `?` are relative to the enclosing closure.
+    let parse = |s: &str| -> Result<u32, ::core::num::ParseIntError> {
+        with_block! {
+            #![with('local)]
+            let n = s.parse::<u32>()?;
+            if n == 0 {
+                return Ok(0);
+            }
+            let s: &'local dyn Display = hex(n);
+            Ok(s.to_string().len() as _)
+        }
+    };
+    assert_eq!(parse("66"), Ok(4));
+    assert_eq!(parse("0"), Ok(0));
+    assert!(parse("not a number").is_err());
+}
+
+#[test]
+#[with('local)]
+fn with_macro ()
+{
+    #[with('local)]
+    fn hex (n: u32) -> &'local dyn Display
+    {
+        &format_args!("{:#x}", n)
+    }
+
+    fn concat (a: &'_ dyn Display, b: &'_ dyn Display)
+      -> String
+    {
+        format!("{}{}", a, b)
+    }
+
+    let mut strings = vec![];
+    for n in 0 .. 3 {
+        if n == 1 {
+            continue;
+        }
+        strings.push(with!(hex(n)).to_string());
+    }
+    assert_eq!(strings, ["0x0", "0x2"]);
+
+    let s = concat(with!(hex(1)), with!(hex(2)));
+    assert_eq!(s, "0x10x2");
+
+    let len = with!(hex(with!(hex(66)).to_string().len() as _)).to_string();
+    assert_eq!(len, "0x4");
+
+    // Conditionally evaluated `with!`s are hoisted within their own block.
+    let mut calls = 0;
+    let mut counted_hex = |n| { calls += 1; n };
+    let s = match 42 {
+        | 0 => with!(hex(counted_hex(0))).to_string(),
+        | n if n > 0 && with!(hex(counted_hex(n))).to_string() == "0x2a" => {
+            "positive".into()
+        },
+        | _ => unreachable!(),
+    };
+    assert_eq!(s, "positive");
+    assert_eq!(calls, 1);
+    let strings = (1 ..= 2).map(|n| with!(hex(n)).to_string()).collect::<Vec<_>>();
+    assert_eq!(strings, ["0x1", "0x2"]);
+
+    // Within the arguments of expression macros.
+    assert_eq!(format!("{}!", with!(hex(3))), "0x3!");
+    assert!(with!(hex(4)).to_string() == "0x4");
+    let v = vec![with!(hex(5)).to_string(); 2];
+    assert_eq!(v, ["0x5", "0x5"]);
+
+    // The hoisted calls are evaluated before the rest of the statement.
+    let order = ::core::cell::RefCell::new(vec![]);
+    let log = |n| { order.borrow_mut().push(n); n };
+    let s = concat(&log(1), with!(hex(log(2))));
+    assert_eq!(s, "10x2");
+    assert_eq!(*order.borrow(), [2, 1]);
+}
+
+#[test]
+#[with('local)]
+fn tail_expressions ()
+{
+    #[with('local)]
+    fn first_even (ns: &'_ [u32]) -> &'local str
+    {
+        let mut i = 0;
+        loop {
+            #[allow(clippy::never_loop)]
+            for _ in 0 .. 1 {
+                // Not the one of the tail `loop`.
+                break;
+            }
+            if ns[i] & 1 == 0 {
+                break &ns[i].to_string();
+            }
+            i += 1;
+        }
+    }
+
+    #[with('local)]
+    fn labelled (n: u32) -> &'local str
+    {
+        'found: {
+            for i in 0 .. n {
+                if i * i == n {
+                    break 'found &format!("{}²", i);
+                }
+            }
+            &n.to_string()
+        }
+    }
+
+    #[with('local)]
+    fn guarded (n: Option<u32>) -> &'local str
+    {
+        if let Some(n) = n {
+            match n {
+                | n if n > 9 => &n.to_string(),
+                | _ => "digit",
+            }
+        } else {
+            "none"
+        }
+    }
+
+    let s: &'local str = first_even(&[1, 3, 42, 5]);
+    assert_eq!(s, "42");
+    let s: &'local str = labelled(49);
+    assert_eq!(s, "7²");
+    let s: &'local str = labelled(50);
+    assert_eq!(s, "50");
+    let s: &'local str = guarded(Some(42));
+    assert_eq!(s, "42");
+    let s: &'local str = guarded(None);
+    assert_eq!(s, "none");
+}
+
+#[test]
+fn auto_return_with_continuation_name ()
+{
+    #[with('local, continuation_name = k, auto_return)]
+    fn hex_or_callback (n: u32, callback: bool) -> &'local str
+    {
+        if callback {
+            // Calling the continuation: left as is.
+            return k(&format!("callback {:#x}", n));
+        }
+        if n == 0 {
+            return "zero";
+        }
+        // Merely mentioning the continuation: rewritten.
+        &format!("{:#x}", n)[.. stringify!(k).len() + 3]
+    }
+
+    assert_eq!(with_hex_or_callback(0, false, str::to_owned), "zero");
+    assert_eq!(with_hex_or_callback(66, false, str::to_owned), "0x42");
+    assert_eq!(with_hex_or_callback(66, true, str::to_owned), "callback 0x42");
+}
+
+#[test]
+fn custom_try_types ()
+{
+    use ::core::{convert::Infallible, ops::ControlFlow, task::Poll};
+    use ::with_locals::{FromResidual, Try};
+
+    #[derive(Debug, PartialEq)]
+    enum Outcome<T> {
+        Success(T),
+        Failure,
+    }
+
+    impl<T> Try for Outcome<T> {
+        type Output = T;
+        type Residual = Outcome<Infallible>;
+
+        fn from_output (output: T)
+          -> Self
+        {
+            Outcome::Success(output)
+        }
+
+        fn branch (self)
+          -> ControlFlow<Outcome<Infallible>, T>
+        {
+            match self {
+                | Outcome::Success(it) => ControlFlow::Continue(it),
+                | Outcome::Failure => ControlFlow::Break(Outcome::Failure),
+            }
+        }
+    }
+
+    impl<T> FromResidual<Outcome<Infallible>> for Outcome<T> {
+        fn from_residual (_: Outcome<Infallible>)
+          -> Self
+        {
+            Outcome::Failure
+        }
+    }
+
+    #[with('local)]
+    fn hex (n: Outcome<u32>) -> Outcome<&'local str>
+    {
+        Outcome::Success(&format!("{:#x}", n?))
+    }
+
+    #[with('local)]
+    fn hex_len (n: Outcome<u32>) -> Outcome<usize>
+    {
+        let s: &'local str = hex(n)?;
+        Outcome::Success(s.len())
+    }
+
+    assert_eq!(hex_len(Outcome::Success(66)), Outcome::Success(4));
+    assert_eq!(hex_len(Outcome::Failure), Outcome::Failure);
+
+    #[with('local)]
+    fn poll_hex (n: Poll<Result<u32, ()>>) -> Poll<Result<&'local str, ()>>
+    {
+        let n = match n? {
+            | Poll::Ready(n) => n,
+            | Poll::Pending => return Poll::Pending,
+        };
+        Poll::Ready(Ok(&format!("{:#x}", n)))
+    }
+
+    let f = |s: Poll<Result<&str, ()>>| s.map_ok(str::to_owned);
+    assert_eq!(with_poll_hex(Poll::Ready(Ok(66)), f), Poll::Ready(Ok("0x42".into())));
+    assert_eq!(with_poll_hex(Poll::Ready(Err(())), f), Poll::Ready(Err(())));
+    assert_eq!(with_poll_hex(Poll::Pending, f), Poll::Pending);
+
+    #[with('local)]
+    fn odd_or_first_even (ns: &'_ [u32]) -> ControlFlow<u32, &'local str>
+    {
+        ns.iter().try_for_each(|&n| if n % 2 == 0 {
+            ControlFlow::Break(n)
+        } else {
+            ControlFlow::Continue(())
+        })?;
+        ControlFlow::Continue(&String::from("all odd"))
+    }
+
+    let f = |it: ControlFlow<u32, &str>| match it {
+        | ControlFlow::Break(n) => n.to_string(),
+        | ControlFlow::Continue(s) => s.to_owned(),
+    };
+    assert_eq!(with_odd_or_first_even(&[1, 2, 4], f), "2");
+    assert_eq!(with_odd_or_first_even(&[1, 3], f), "all odd");
+}
+
+#[test]
+fn hand_written_cps ()
+{
+    use ::with_locals::cps::ControlFlow;
+
+    #[with('local)]
+    fn hex (n: u32) -> &'local str
+    {
+        &format!("{:#x}", n)
+    }
+
+    fn hexes (ns: &'_ [u32]) -> Result<Vec<String>, u32>
+    {
+        let mut acc = vec![];
+        for &n in ns {
+            let s = propagate!(loop, with_hex(n, |s| {
+                match n {
+                    | 0 => cps_continue!(),
+                    | 1 => cps_break!(),
+                    | 2 => cps_return!(Err(n)),
+                    | _ => ControlFlow::Eval(s.to_owned()),
+                }
+            }));
+            acc.push(s);
+        }
+        Ok(acc)
+    }
+
+    assert_eq!(hexes(&[66, 0, 255]), Ok(vec!["0x42".into(), "0xff".into()]));
+    assert_eq!(hexes(&[66, 1, 255]), Ok(vec!["0x42".into()]));
+    assert_eq!(hexes(&[66, 2, 255]), Err(2));
+
+    fn len (n: u32) -> usize
+    {
+        let len = propagate!(with_hex(n, |s| {
+            if n == 0 {
+                cps_return!(0);
+            }
+            ControlFlow::Eval(s.len())
+        }));
+        len - "0x".len()
+    }
+
+    assert_eq!(len(0), 0);
+    assert_eq!(len(66), 2);
+
+    fn first_with_a_letter (ns: &'_ [u32]) -> Option<String>
+    {
+        let mut ns = ns.iter();
+        loop {
+            let &n = ns.next()?;
+            propagate!(break, with_hex(n, |s| {
+                if n == 0 {
+                    cps_return!(None);
+                }
+                if s.bytes().any(|b| b.is_ascii_hexdigit() && b > b'9') {
+                    cps_break!(Some(s.to_owned()));
+                }
+                cps_continue!();
+            }))
+        }
+    }
+
+    assert_eq!(first_with_a_letter(&[1, 9, 10, 11]).as_deref(), Some("0xa"));
+    assert_eq!(first_with_a_letter(&[1, 0, 10]), None);
+    assert_eq!(first_with_a_letter(&[1, 2]), None);
+}
+
+#[test]
+#[with('local)]
+fn explicit_continuation_types ()
+{
+    #[with('local)]
+    fn show<T : Display> (it: T) -> &'local str
+    {
+        &it.to_string()
+    }
+
+    let len = {
+        #[with(ret = u64)]
+        let s = show::<u32>(66);
+        s.len() as _
+    };
+    assert_eq!(len.count_ones(), 1);
+
+    #[with]
+    fn first_long (words: &'_ [&'_ str]) -> Result<String, usize>
+    {
+        let mut i = 0;
+        let end = loop {
+            if i == words.len() {
+                break i;
+            }
+            #[with(return = Result<String, _>, break = usize)]
+            let s: &str = show(words[i]);
+            if s.is_empty() {
+                break i;
+            }
+            if s.len() > 3 {
+                return Ok(s.to_owned());
+            }
+            i += 1;
+        };
+        Err(end)
+    }
+
+    assert_eq!(first_long(&["a", "long", "b"]), Ok("long".into()));
+    assert_eq!(first_long(&["a", "", "long"]), Err(1));
+    assert_eq!(first_long(&["a"]), Err(1));
+}
+
+#[test]
+fn macro_args ()
+{
+    use ::core::num::ParseIntError;
+
+    macro_rules! ensure {( $cond:expr ) => (
+        assert!($cond)
+    )}
+
+    #[with('local, macros = [ensure])]
+    fn digits (s: &'_ str) -> Result<&'local [u32], ParseIntError>
+    {
+        if matches!(s.parse::<u32>()?, 0) {
+            return Ok(&[]);
+        }
+        let n: u32 = s.parse()?;
+        #[allow(clippy::useless_vec)]
+        let twice = vec![s.parse::<u32>()?; 2];
+        assert_eq!(format!("{}", s.parse::<u32>()?), s);
+        ensure!(s.parse::<u32>()? < 100);
+        Ok(&[twice[0] / 10, n % 10])
+    }
+
+    #[with('local)]
+    fn sum (s: &'_ str) -> Result<u32, ParseIntError>
+    {
+        let ds: &'local [u32] = digits(s)?;
+        assert!(ds.len() <= s.parse::<u32>()?.to_string().len());
+        Ok(ds.iter().sum())
+    }
+
+    assert_eq!(sum("42"), Ok(6));
+    assert_eq!(sum("0"), Ok(0));
+    assert!(sum("nope").is_err());
+}
+
+#[test]
+#[deny(unused_variables)]
+#[with('local)]
+fn with_let_attrs ()
+{
+    #[with('local)]
+    fn hex (n: u32) -> &'local dyn Display
+    {
+        &format_args!("{:#x}", n)
+    }
+
+    let mut s = String::new();
+    #[cfg(any())]
+    #[with]
+    let s_hex = hex(1);
+    #[cfg(test)]
+    #[with]
+    let s_hex = hex(2);
+    s += &s_hex.to_string();
+    #[allow(unused_variables)]
+    #[with]
+    let unused = hex(3);
+    #[cfg(any())]
+    let s_hex: &'local dyn Display = hex(4);
+    s += &s_hex.to_string();
+    assert_eq!(s, "0x20x2");
+
+    // Nested ones, with the continuation being used in both cases.
+    let mut strings = vec![];
+    for n in 5 .. 7 {
+        #[cfg(test)]
+        #[with]
+        let a = hex(n);
+        #[cfg(any())]
+        #[with]
+        let b = hex(n + 1);
+        #[cfg(not(any()))]
+        let b = "none";
+        if n == 5 {
+            continue;
+        }
+        strings.push(format!("{} {}", a, b));
+    }
+    assert_eq!(strings, ["0x6 none"]);
+
+    // A `?`-unwrapped one, whose unwrapping is to be `cfg`-ed out along it.
+    #[with('local)]
+    fn checked_hex (n: u32) -> Result<&'local str, ()>
+    {
+        Ok(&format!("{:#x}", n))
+    }
+
+    #[with('local)]
+    fn len_of (n: u32) -> Result<usize, ()>
+    {
+        #[cfg(any())]
+        #[with]
+        let s: &'local str = checked_hex(n + 1)?;
+        #[cfg(test)]
+        #[allow(unused_mut)]
+        #[with]
+        let mut s: &'local str = checked_hex(n)?;
+        Ok(s.len())
+    }
+    assert_eq!(len_of(0x10), Ok(4));
+}
+
+#[test]
+#[with('local)]
+fn with_mod ()
+{
+    #[with('local)]
+    mod views {
+        use ::core::fmt::Display;
+
+        pub
+        fn hex (n: u32) -> &'local dyn Display
+        {
+            &format_args!("{:#x}", n)
+        }
+
+        pub
+        struct Wrapper(pub u32);
+
+        impl Wrapper {
+            pub
+            fn view (self: &'_ Self) -> &'local dyn Display
+            {
+                &self.0
+            }
+        }
+
+        pub
+        trait ToStr {
+            fn to_str (self: &'_ Self) -> &'local str;
+        }
+
+        impl ToStr for u32 {
+            fn to_str (self: &'_ Self) -> &'local str
+            {
+                &self.to_string()
+            }
+        }
+
+        pub
+        mod nested {
+            #[with('special)]
+            pub
+            fn octal (n: u32) -> &'special str
+            {
+                &format!("{:#o}", n)
+            }
+
+            #[with(skip)]
+            pub
+            fn untouched () -> &'static str
+            {
+                "'local"
+            }
+        }
+    }
+    use views::ToStr as _;
+
+    let s: &'local dyn Display = views::hex(66);
+    assert_eq!(s.to_string(), "0x42");
+    let s: &'local dyn Display = views::Wrapper(42).view();
+    assert_eq!(s.to_string(), "42");
+    let s: &'local str = 42.to_str();
+    assert_eq!(s, "42");
+    let s = views::nested::with_octal(8, |s| s.to_owned());
+    assert_eq!(s, "0o10");
+    assert_eq!(views::nested::untouched(), "'local");
+}
+
+#[test]
+#[with('local)]
+fn mutually_recursive_implied ()
+{
+    #[with('local)]
+    mod parity {
+        pub
+        fn is_even (n: u32) -> &'local bool
+        {
+            if n == 0 {
+                return &true;
+            }
+            let odd: &'local bool = is_odd(n - 1);
+            let even = *odd;
+            &even
+        }
+
+        pub
+        fn is_odd (n: u32) -> &'local bool
+        {
+            if n == 0 {
+                return &false;
+            }
+            let even: &'local bool = is_even(n - 1);
+            let odd = *even;
+            &odd
+        }
+    }
+    let even: &'local bool = parity::is_even(27);
+    assert!(!*even);
+}
+
+#[test]
+#[with('local)]
+fn continuation_bounds ()
+{
+    #[with('local, continuation_name = k, continuation_bounds = Send)]
+    fn on_scoped_thread (n: u32) -> &'local str
+    {
+        let s = n.to_string();
+        let s = &*s;
+        ::std::thread::scope(|scope| {
+            scope.spawn(move || k(s)).join().unwrap()
+        })
+    }
+
+    #[with('local, continuation_name = k, continuation_bounds = Send + 'static)]
+    fn on_spawned_thread (n: u32) -> &'local str
+    {
+        ::std::thread::spawn(move || k(&n.to_string())).join().unwrap()
+    }
+
+    let s: &'local str = on_scoped_thread(42);
+    assert_eq!(s, "42");
+    let expected = String::from("27");
+    #[with(move)]
+    let s: &'local str = on_spawned_thread(27);
+    assert_eq!(s, expected);
+}
+
+#[test]
+#[with('local)]
+fn custom_names ()
+{
+    #[with('local, name = "visit_{}")]
+    mod views {
+        use super::*;
+
+        pub
+        fn hex (n: u32) -> &'local dyn Display
+        {
+            &format_args!("{:#x}", n)
+        }
+    }
+    use views::*;
+
+    struct View(u32);
+    impl View {
+        #[with('local, rename = scoped_view)]
+        fn view (self: &'_ Self) -> &'local dyn Display
+        {
+            &self.0
+        }
+    }
+
+    // Through the generated `with_hex` alias.
+    #[with]
+    let s_hex = hex(66);
+    assert_eq!(s_hex.to_string(), "0x42");
+    let s_hex = visit_hex(66, |s| s.to_string());
+    assert_eq!(s_hex, "0x42");
+
+    // Methods get no alias: the call site names the function.
+    #[with(rename = scoped_view)]
+    let s_view = View(42).view();
+    assert_eq!(s_view.to_string(), "42");
+}
+
+/// A crate re-exporting `#[with]` would have its users depend on it only.
+mod facade {
+    pub use ::with_locals::*;
+}
+
+#[test]
+fn custom_crate_path ()
+{
+    #[with('local, crate = crate::facade)]
+    fn hex (n: u32) -> &'local dyn Display
+    {
+        &format_args!("{:#x}", n)
+    }
+
+    #[with('local, dyn_safe = true, crate = crate::facade)]
+    trait DynSafe {
+        fn foo (&self) -> &'local ()
+        {
+            &()
+        }
+    }
+    impl DynSafe for () {}
+
+    #[with('local, crate = crate::facade)]
+    fn check ()
+    {
+        #[with]
+        let s_hex = hex(66);
+        assert_eq!(s_hex.to_string(), "0x42");
+        let dyn_safe: &dyn DynSafe = &();
+        #[with(dyn_safe)]
+        let _: &'local () = dyn_safe.foo();
+    }
+    check();
+}
+
 #[test]
 #[with('local)]
 fn object_safe ()
@@ -253,3 +967,32 @@ fn object_safe ()
     let _: &'local () = dyn_obj.foo();
     return;
 }
+
+#[cfg(feature = "alloc")]
+#[test]
+#[with('local)]
+fn object_safe_boxed ()
+{
+    #[with('local, dyn_safe = "boxed")]
+    trait DynSafe {
+        fn foo (&self) -> &'local str;
+    }
+    impl DynSafe for u32 {
+        #[with('local, dyn_safe = "boxed")]
+        fn foo (&self) -> &'local str
+        {
+            &self.to_string()
+        }
+    }
+    let dyn_obj: &'_ dyn DynSafe = &42;
+    let owned = String::from("owned");
+    let owned_len = {
+        #[with(dyn_safe = "boxed")]
+        let s: &'local str = dyn_obj.foo();
+        assert_eq!(s, "42");
+        // Only compiles with a genuine `FnOnce` continuation.
+        let moved: String = owned;
+        moved.len()
+    };
+    assert_eq!(owned_len, 5);
+}
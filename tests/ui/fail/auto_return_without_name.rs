@@ -0,0 +1,23 @@
+include!("../prelude.rs");
+
+#[with('local, auto_return = false)]
+fn hex (n: u32) -> &'local str
+{
+    &format!("{:#x}", n)
+}
+
+struct Foo;
+
+#[with('local, auto_return = false)]
+impl Foo {
+    #[with('local, continuation_name = k)]
+    fn to_str (self: &'_ Self) -> &'local str
+    {
+        k("Foo")
+    }
+
+    fn name (self: &'_ Self) -> &'static str
+    {
+        "Foo"
+    }
+}
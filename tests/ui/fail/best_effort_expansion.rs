@@ -0,0 +1,28 @@
+include!("../prelude.rs");
+
+use ::core::fmt::Display;
+
+// The mistakes below are reported, but the `with_`-prefixed signatures are
+// still emitted, so that their callers do not error too.
+
+#[with('local, dyn_safe = 42)]
+fn hex (n: u32) -> &'local dyn Display
+{
+    &format_args!("{:#x}", n)
+}
+
+#[with('local)]
+fn oct (n: u32) -> &'local dyn Display
+{
+    #[with]
+    let _incomplete: u8;
+    &format_args!("{:#o}", n)
+}
+
+#[with('local)]
+fn callers ()
+{
+    let h: &'local dyn Display = hex(66);
+    let o: &'local dyn Display = oct(66);
+    println!("{} {}", h, o);
+}
@@ -0,0 +1,18 @@
+include!("../prelude.rs");
+
+#[with('local)]
+fn hex (n: u32) -> &'local dyn ::core::fmt::Display
+{
+    &format_args!("{:#x}", n)
+}
+
+#[with]
+fn conditional_with ()
+{
+    #[cfg_attr(all(), with)]
+    let a = hex(1);
+    #[cfg_attr(all(), cfg(all()))]
+    #[with]
+    let b = hex(2);
+    let _ = (a, b);
+}
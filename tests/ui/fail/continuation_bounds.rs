@@ -0,0 +1,19 @@
+include!("../prelude.rs");
+
+#[with('local, continuation_bounds = Send + 'static, recursive = true)]
+fn recursive (n: u32) -> &'local u32
+{
+    &n
+}
+
+#[with('local, continuation_bounds = 'static, dyn_safe = true)]
+fn dyn_safe (n: u32) -> &'local u32
+{
+    &n
+}
+
+#[with('local, continuation_bounds = Send, dyn_safe = true)]
+fn dyn_safe_send (n: u32) -> &'local u32
+{
+    &n
+}
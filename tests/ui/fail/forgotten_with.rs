@@ -0,0 +1,16 @@
+include!("../prelude.rs");
+
+#[with('local)]
+mod views {
+    pub
+    fn hex (n: u32) -> &'local dyn ::core::fmt::Display
+    {
+        &format_args!("{:#x}", n)
+    }
+}
+
+fn main_ ()
+{
+    let s = views::hex(66);
+    println!("{}", s);
+}
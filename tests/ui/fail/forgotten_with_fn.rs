@@ -0,0 +1,27 @@
+include!("../prelude.rs");
+
+#[with('local)]
+pub(crate)
+fn hex (n: u32) -> &'local dyn ::core::fmt::Display
+{
+    &format_args!("{:#x}", n)
+}
+
+struct Octal;
+
+impl Octal {
+    #[with('local)]
+    pub(self)
+    fn octal (n: u32) -> &'local dyn ::core::fmt::Display
+    {
+        &format_args!("{:#o}", n)
+    }
+}
+
+fn main_ ()
+{
+    let s = hex(66);
+    println!("{}", s);
+    let s = Octal::octal(66);
+    println!("{}", s);
+}
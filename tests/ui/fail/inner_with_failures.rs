@@ -40,3 +40,10 @@ fn bad_rhs_has_attr ()
     #[with]
     let _ = #[extraneous] foo();
 }
+
+#[with]
+fn explicit_types_without_break_nor_return ()
+{
+    #[with(return = (), break = u8)]
+    let _ = foo();
+}
@@ -0,0 +1,24 @@
+include!("../prelude.rs");
+
+#[with]
+fn all_errors_are_reported ()
+{
+    #[with]
+    let _incomplete;
+
+    #[with(dyn_safe = 42)]
+    let _ = foo();
+
+    for _ in 0 .. 1 {
+        #[with]
+        let _ = 42;
+        #[with]
+        print!();
+    }
+
+    'outer: loop {
+        #[with]
+        let _ = bar();
+        break 'outer;
+    }
+}
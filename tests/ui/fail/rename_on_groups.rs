@@ -0,0 +1,27 @@
+include!("../prelude.rs");
+
+struct Foo;
+
+#[with('local, rename = scoped_to_str)]
+impl Foo {
+    fn to_str (self: &'_ Self) -> &'local str
+    {
+        "Foo"
+    }
+}
+
+#[with('local, rename = scoped_to_str)]
+trait ToStr {
+    fn to_str (self: &'_ Self) -> &'local str;
+}
+
+#[with('local)]
+mod m {
+    #[with('local, rename = scoped_view)]
+    mod inner {
+        fn view () -> &'local str
+        {
+            "view"
+        }
+    }
+}
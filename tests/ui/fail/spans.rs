@@ -0,0 +1,38 @@
+include!("../prelude.rs");
+
+// Errors in the code generated for `?`, `return` and the continuations are
+// reported at the offending user code, not at the `#[with]` attribute.
+
+struct NotAnIoError;
+
+#[with('local)]
+fn read () -> ::std::io::Result<&'local str>
+{
+    let contents: Result<String, NotAnIoError> = Err(NotAnIoError);
+    let contents = contents?;
+    Ok(&contents)
+}
+
+#[with('local)]
+fn caller () -> ::std::io::Result<u8>
+{
+    let s: &'local str = read()?;
+    let n: u8 = s.parse()?;
+    Ok(n)
+}
+
+#[with('local)]
+fn name () -> &'local str
+{
+    &String::from("name")
+}
+
+#[with('local)]
+fn early_return () -> u8
+{
+    let s: &'local str = name();
+    if s.is_empty() {
+        return "empty";
+    }
+    42
+}
@@ -0,0 +1,22 @@
+#![deny(unreachable_code, unused_parens)]
+
+include!("../prelude.rs");
+
+// Only the user code gets linted: the generated code does not trigger any lint,
+// and does not silence the lints of the user code either.
+
+#[with('local)]
+fn f () -> &'local u8
+{
+    return &0;
+    &1
+}
+
+#[with('local)]
+fn g () -> u8
+{
+    let x: &'local u8 = f();
+    let y = (*x);
+    return y;
+    42
+}
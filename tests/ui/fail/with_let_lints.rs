@@ -0,0 +1,30 @@
+#![deny(unused_variables)]
+
+include!("../prelude.rs");
+
+#[with('local)]
+fn hex (n: u32) -> &'local str
+{
+    &format!("{:#x}", n)
+}
+
+#[with('local)]
+fn checked_hex (n: u32) -> Result<&'local str, ()>
+{
+    Ok(&format!("{:#x}", n))
+}
+
+#[with('local)]
+fn lints () -> Result<(), ()>
+{
+    // The lint attributes apply to the binding ...
+    #[allow(unused_variables)]
+    #[with]
+    let unused = hex(1);
+    #[allow(unused_variables)]
+    #[with]
+    let unused_too: &'local str = checked_hex(2)?;
+    // ... and not to the statements that follow.
+    let later = 3;
+    Ok(())
+}
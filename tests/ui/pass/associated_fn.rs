@@ -0,0 +1,17 @@
+include!("../prelude.rs");
+
+struct Hex;
+
+// A `#[with] fn` without `self` within a non-annotated `impl` block.
+impl Hex {
+    #[with('local)]
+    fn make (n: u32) -> &'local str
+    {
+        &format!("{:#x}", n)
+    }
+}
+
+fn main_ ()
+{
+    Hex::with_make(66, |s| assert_eq!(s, "0x42"));
+}
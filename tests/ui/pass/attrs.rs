@@ -130,4 +130,11 @@ const _: () = {
         fn __ ()
         {}
     };
+    const _: () = {
+        #[with(
+            'some_name , continuation_bounds = Send + Sync , recursive ,
+        )]
+        fn __ ()
+        {}
+    };
 };
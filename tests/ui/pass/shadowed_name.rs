@@ -0,0 +1,36 @@
+include!("../prelude.rs");
+
+#[with('local)]
+mod views {
+    pub
+    fn hex (n: u32) -> &'local str
+    {
+        &format!("{:#x}", n)
+    }
+
+    // Mutually exclusive definitions, along with their shadow items.
+    #[cfg(debug_assertions)]
+    pub
+    fn octal (n: u32) -> &'local str
+    {
+        &format!("{:#o}", n)
+    }
+
+    #[cfg_attr(debug_assertions, cfg(any()))]
+    pub
+    fn octal (n: u32) -> &'local str
+    {
+        &format!("{:o}", n)
+    }
+}
+
+use views::{with_hex, with_octal};
+
+// Bindings may still be named after a with-function.
+fn main_ ()
+{
+    let hex = with_hex(66, |hex| hex.to_owned());
+    assert_eq!(hex, "0x42");
+    let octal = with_octal(8, |octal| octal.to_owned());
+    assert!(octal.ends_with("10"));
+}
@@ -0,0 +1,10 @@
+include!("../prelude.rs");
+
+#[with('local)]
+fn unsafe_tail (n: u32) -> &'local str
+{
+    #[allow(unused_unsafe)]
+    unsafe {
+        &n.to_string()
+    }
+}